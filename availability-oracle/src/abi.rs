@@ -0,0 +1,109 @@
+//! Checks that the handlers of a mapping match the ABI of its data source, since a handler for an
+//! event or function the contract doesn't have is never triggered.
+
+use crate::manifest::Mapping;
+use crate::Invalid;
+use ethabi::{Contract, Event, Function};
+
+/// Checks the event and call handlers of `mapping` against `contract`, the ABI named `abi`.
+pub fn check_handlers(mapping: &Mapping, abi: &str, contract: &Contract) -> Result<(), Invalid> {
+    for handler in &mapping.event_handlers {
+        let found = contract.events().any(|event| {
+            let (signature, indexed_signature) = event_signatures(event);
+            handler.event == signature || handler.event == indexed_signature
+        });
+        if !found {
+            return Err(Invalid::MissingAbiEvent(
+                abi.to_string(),
+                handler.event.clone(),
+            ));
+        }
+    }
+
+    for handler in &mapping.call_handlers {
+        if !contract
+            .functions()
+            .any(|function| handler.function == function_signature(function))
+        {
+            return Err(Invalid::MissingAbiFunction(
+                abi.to_string(),
+                handler.function.clone(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The signature of an event, such as `Transfer(address,address,uint256)`, and the same with the
+/// indexed parameters marked, such as `Transfer(indexed address,indexed address,uint256)`.
+/// Manifests may use either.
+fn event_signatures(event: &Event) -> (String, String) {
+    let params: Vec<String> = event.inputs.iter().map(|p| p.kind.to_string()).collect();
+    let indexed_params: Vec<String> = event
+        .inputs
+        .iter()
+        .map(|p| match p.indexed {
+            true => format!("indexed {}", p.kind),
+            false => p.kind.to_string(),
+        })
+        .collect();
+    (
+        format!("{}({})", event.name, params.join(",")),
+        format!("{}({})", event.name, indexed_params.join(",")),
+    )
+}
+
+/// The signature of a function, such as `transfer(address,uint256)`.
+fn function_signature(function: &Function) -> String {
+    let params: Vec<String> = function.inputs.iter().map(|p| p.kind.to_string()).collect();
+    format!("{}({})", function.name, params.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABI: &[u8] = include_bytes!("test_files/Contract.abi");
+
+    fn mapping(events: &[&str], functions: &[&str]) -> Mapping {
+        let mapping = serde_json::json!({
+            "eventHandlers": events
+                .iter()
+                .map(|event| serde_json::json!({ "event": event, "handler": "handleEvent" }))
+                .collect::<Vec<_>>(),
+            "callHandlers": functions
+                .iter()
+                .map(|function| serde_json::json!({ "function": function, "handler": "handleCall" }))
+                .collect::<Vec<_>>(),
+        });
+        serde_json::from_value(mapping).unwrap()
+    }
+
+    #[test]
+    fn test_check_handlers() {
+        let contract = Contract::load(ABI).unwrap();
+
+        assert!(check_handlers(
+            &mapping(&["Trigger(uint16)"], &["emitTrigger(uint16)"]),
+            "Contract",
+            &contract
+        )
+        .is_ok());
+
+        match check_handlers(&mapping(&["Trigger(uint32)"], &[]), "Contract", &contract) {
+            Err(Invalid::MissingAbiEvent(abi, event)) => {
+                assert_eq!(abi, "Contract");
+                assert_eq!(event, "Trigger(uint32)");
+            }
+            _ => panic!("expected a missing event"),
+        }
+
+        match check_handlers(&mapping(&[], &["emitTrigger()"]), "Contract", &contract) {
+            Err(Invalid::MissingAbiFunction(_, function)) => {
+                assert_eq!(function, "emitTrigger()")
+            }
+            _ => panic!("expected a missing function"),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use common::prelude::*;
+use common::prometheus;
+use moka::future::Cache;
+use reqwest::Client;
+use std::time::Duration;
+
+/// Length of an Arweave transaction ID, 32 bytes in unpadded base64url.
+const TX_ID_LEN: usize = 43;
+
+pub enum ArweaveError {
+    Timeout(String, Error),     // Gateway or client timed-out
+    NotFound(String, Error),    // Transaction not found
+    TooLarge(String, u64, u64), // File is larger than the limit, with the size seen and the limit
+    Other(Error),
+}
+
+impl ArweaveError {
+    fn kind(&self) -> &'static str {
+        match self {
+            ArweaveError::Timeout(..) => "timeout",
+            ArweaveError::NotFound(..) => "not_found",
+            ArweaveError::TooLarge(..) => "too_large",
+            ArweaveError::Other(_) => "other",
+        }
+    }
+}
+
+impl std::fmt::Display for ArweaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArweaveError::Timeout(tx_id, e) => write!(f, "timeout for {}: {}", tx_id, e),
+            ArweaveError::NotFound(tx_id, e) => write!(f, "not found {}: {}", tx_id, e),
+            ArweaveError::TooLarge(tx_id, size, limit) => write!(
+                f,
+                "file too large: {} ({} bytes, limit {})",
+                tx_id, size, limit
+            ),
+            ArweaveError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The transaction ID a link to an Arweave file refers to. Links are either a bare transaction
+/// ID or one prefixed with `ar://`.
+pub fn tx_id(link: &str) -> Option<&str> {
+    let tx_id = link.strip_prefix("ar://").unwrap_or(link);
+    let valid = tx_id.len() == TX_ID_LEN
+        && tx_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    valid.then_some(tx_id)
+}
+
+/// All Arweave interactions required by the oracle.
+#[async_trait]
+pub trait Arweave {
+    /// Download the data of a transaction, failing with `ArweaveError::TooLarge` if it's larger
+    /// than `max_size` bytes.
+    async fn get(&self, tx_id: &str, max_size: u64) -> Result<Bytes, ArweaveError>;
+
+    /// Invalidate cache of transactions
+    fn invalidate_cache(&self);
+}
+
+/// Fetches transaction data from an HTTP gateway, such as `https://arweave.net`.
+pub struct ArweaveImpl {
+    gateway: String,
+    client: Client,
+
+    // Cache for transactions, weighed by file size; we invalidate this cache between runs to
+    // ensure we're checking Arweave regularly
+    cache: Cache<String, Bytes>,
+
+    // If the request times out, the file is considered unavailable.
+    timeout: Duration,
+}
+
+impl ArweaveImpl {
+    /// `cache_size` is the total size, in bytes, of the files kept in the cache.
+    pub fn new(gateway: String, timeout: Duration, cache_size: u64) -> Self {
+        ArweaveImpl {
+            gateway,
+            client: Client::new(),
+            cache: Cache::builder()
+                .weigher(|_, bytes: &Bytes| u32::try_from(bytes.len()).unwrap_or(u32::MAX))
+                .max_capacity(cache_size)
+                .build(),
+            timeout,
+        }
+    }
+
+    async fn fetch(&self, tx_id: &str, max_size: u64) -> Result<Bytes, ArweaveError> {
+        METRICS.arweave_requests_total.inc();
+        let url = format!("{}/{}", self.gateway.trim_end_matches('/'), tx_id);
+        let res = self
+            .client
+            .get(url)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(|e| {
+                if e.is_timeout() {
+                    ArweaveError::Timeout(tx_id.to_string(), e.into())
+                } else {
+                    ArweaveError::Other(e.into())
+                }
+            })?
+            .error_for_status()
+            .map_err(|e| match e.status().map(|e| e.as_u16()) {
+                Some(GATEWAY_TIMEOUT) | Some(CLOUDFLARE_TIMEOUT) => {
+                    ArweaveError::Timeout(tx_id.to_string(), e.into())
+                }
+                Some(NOT_FOUND) => ArweaveError::NotFound(tx_id.to_string(), e.into()),
+                _ => ArweaveError::Other(e.into()),
+            })?;
+        read_body(res, tx_id, max_size).await
+    }
+}
+
+/// Reads the body of a response, giving up as soon as it's known to be larger than `max_size`.
+async fn read_body(
+    mut res: reqwest::Response,
+    tx_id: &str,
+    max_size: u64,
+) -> Result<Bytes, ArweaveError> {
+    if let Some(len) = res.content_length().filter(|len| *len > max_size) {
+        return Err(ArweaveError::TooLarge(tx_id.to_string(), len, max_size));
+    }
+
+    let mut body = BytesMut::new();
+    while let Some(chunk) = res
+        .chunk()
+        .await
+        .map_err(|e| ArweaveError::Other(e.into()))?
+    {
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > max_size {
+            return Err(ArweaveError::TooLarge(
+                tx_id.to_string(),
+                body.len() as u64,
+                max_size,
+            ));
+        }
+    }
+    Ok(body.freeze())
+}
+
+const CLOUDFLARE_TIMEOUT: u16 = 524;
+const GATEWAY_TIMEOUT: u16 = 504;
+const NOT_FOUND: u16 = 404;
+
+#[async_trait]
+impl Arweave for ArweaveImpl {
+    async fn get(&self, tx_id: &str, max_size: u64) -> Result<Bytes, ArweaveError> {
+        if let Some(cached_bytes) = self.cache.get(tx_id).await {
+            METRICS.arweave_cache_hits.inc();
+            if cached_bytes.len() as u64 > max_size {
+                return Err(ArweaveError::TooLarge(
+                    tx_id.to_string(),
+                    cached_bytes.len() as u64,
+                    max_size,
+                ));
+            }
+            return Ok(cached_bytes);
+        }
+
+        let bytes = self.fetch(tx_id, max_size).await.inspect_err(|e| {
+            METRICS
+                .arweave_errors_total
+                .with_label_values(&[e.kind()])
+                .inc();
+        })?;
+        self.cache.insert(tx_id.to_string(), bytes.clone()).await;
+        Ok(bytes)
+    }
+
+    fn invalidate_cache(&self) {
+        self.cache.invalidate_all();
+    }
+}
+
+struct Metrics {
+    arweave_requests_total: prometheus::IntCounter,
+    arweave_errors_total: prometheus::IntCounterVec,
+    arweave_cache_hits: prometheus::IntCounter,
+}
+
+lazy_static! {
+    static ref METRICS: Metrics = Metrics::new();
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Self {
+            arweave_requests_total: prometheus::register_int_counter!(
+                "arweave_requests_total",
+                "Total arweave requests"
+            )
+            .unwrap(),
+            arweave_errors_total: prometheus::register_int_counter_vec!(
+                "arweave_errors_total",
+                "Total failed arweave requests, by error kind",
+                &["kind"]
+            )
+            .unwrap(),
+            arweave_cache_hits: prometheus::register_int_counter!(
+                "arweave_cache_hits",
+                "Total arweave cache hits"
+            )
+            .unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tx_id() {
+        let id = "Yk8Pbd3mLfMp0UHxRQ-BaQXkJhGdzcEDqWnzhh4JQsI";
+        assert_eq!(tx_id(id), Some(id));
+        assert_eq!(tx_id(&format!("ar://{}", id)), Some(id));
+        assert_eq!(
+            tx_id("QmWt3abizzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz"),
+            None
+        );
+        assert_eq!(tx_id(&id[1..]), None);
+        assert_eq!(tx_id(&id.replace('-', "+")), None);
+    }
+}
@@ -0,0 +1,350 @@
+//! Verification of files fetched as CAR (Content Addressable aRchive) files, so that a gateway
+//! can't serve contents that don't match the requested CID.
+
+use crate::proto::{fields, read_varint, Field};
+use bytes::Bytes;
+use common::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tiny_cid::Cid;
+
+const DAG_PB: u64 = 0x70;
+const RAW: u64 = 0x55;
+const IDENTITY: u64 = 0x00;
+const SHA2_256: u64 = 0x12;
+
+// UnixFS data types that hold file contents.
+const UNIXFS_RAW: u64 = 0;
+const UNIXFS_FILE: u64 = 2;
+
+// Bound the recursion and the work on malicious DAGs, which may link the same block many times.
+const MAX_DEPTH: usize = 32;
+const MAX_BLOCK_VISITS: usize = 1_000_000;
+
+#[derive(Debug)]
+pub enum CarError {
+    /// The file is larger than the limit, with the size reached when giving up.
+    TooLarge(u64),
+    /// The file is not a UnixFS file this module can verify, such as one with a codec or hash
+    /// function other than those of UnixFS files. This is a property of the requested CID, not
+    /// of the endpoint serving it.
+    Unsupported(Error),
+    /// The CAR is malformed or lacks blocks of the file, so it doesn't hold the requested file.
+    Mismatch(Error),
+}
+
+fn unsupported(e: impl Into<Error>) -> CarError {
+    CarError::Unsupported(e.into())
+}
+
+/// Checks that every block in the CAR hashes to its CID, and reassembles the UnixFS file rooted
+/// at `root` from those blocks, giving up once the file is larger than `max_size`.
+pub fn verified_file(root: Cid, car: &[u8], max_size: u64) -> Result<Bytes, CarError> {
+    let blocks = verified_blocks(car).map_err(CarError::Mismatch)?;
+    let mut file = Vec::new();
+    let mut visits = 0;
+    append_file(&root, &blocks, &mut file, max_size, &mut visits, 0)?;
+    Ok(file.into())
+}
+
+/// The blocks of the CAR that hash to their CID. Blocks with a hash function other than sha2-256
+/// can't be checked and are left out, which is only an error if the file needs them.
+fn verified_blocks(mut car: &[u8]) -> Result<HashMap<Cid, &[u8]>, Error> {
+    // The header holds the roots and version, the requested CID is used as the root instead.
+    let header_len = read_varint(&mut car)? as usize;
+    ensure!(header_len <= car.len(), "truncated CAR header");
+    car = &car[header_len..];
+
+    let mut blocks = HashMap::new();
+    while !car.is_empty() {
+        let section_len = read_varint(&mut car)? as usize;
+        ensure!(section_len <= car.len(), "truncated CAR section");
+        let (mut section, rest) = car.split_at(section_len);
+        car = rest;
+
+        let cid = Cid::read_bytes(&mut section)?;
+        let data = section;
+        let hash = cid.hash();
+        if hash.code() != SHA2_256 {
+            continue;
+        }
+        ensure!(
+            hash.digest() == Sha256::digest(data).as_slice(),
+            "block {} does not match its hash",
+            cid
+        );
+        blocks.insert(block_key(&cid), data);
+    }
+    Ok(blocks)
+}
+
+/// The CIDv1 of a CID, so that a CIDv0 and a dag-pb CIDv1 of the same block find the same block.
+fn block_key(cid: &Cid) -> Cid {
+    Cid::new_v1(cid.codec(), *cid.hash())
+}
+
+fn append_file(
+    cid: &Cid,
+    blocks: &HashMap<Cid, &[u8]>,
+    file: &mut Vec<u8>,
+    max_size: u64,
+    visits: &mut usize,
+    depth: usize,
+) -> Result<(), CarError> {
+    *visits += 1;
+    if depth > MAX_DEPTH {
+        return Err(unsupported(anyhow!("UnixFS DAG is too deep")));
+    }
+    if *visits > MAX_BLOCK_VISITS {
+        return Err(unsupported(anyhow!("UnixFS DAG has too many blocks")));
+    }
+
+    // Identity CIDs hold their block inline.
+    let block = match cid.hash().code() {
+        IDENTITY => cid.hash().digest(),
+        SHA2_256 => *blocks
+            .get(&block_key(cid))
+            .ok_or_else(|| CarError::Mismatch(anyhow!("block {} is missing from the CAR", cid)))?,
+        code => {
+            return Err(unsupported(anyhow!(
+                "unsupported hash function {:#x} in block {}",
+                code,
+                cid
+            )))
+        }
+    };
+
+    match cid.codec() {
+        RAW => file.extend_from_slice(block),
+        DAG_PB => {
+            let mut links = Vec::new();
+            let mut unixfs = None;
+            for field in fields(block) {
+                match field.map_err(unsupported)? {
+                    (1, Field::Bytes(data)) => unixfs = Some(data),
+                    (2, Field::Bytes(link)) => {
+                        for field in fields(link) {
+                            if let (1, Field::Bytes(hash)) = field.map_err(unsupported)? {
+                                links.push(Cid::read_bytes(hash).map_err(unsupported)?);
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            let mut data_type = None;
+            let mut data: &[u8] = &[];
+            for field in fields(unixfs.unwrap_or_default()) {
+                match field.map_err(unsupported)? {
+                    (1, Field::Varint(t)) => data_type = Some(t),
+                    (2, Field::Bytes(d)) => data = d,
+                    _ => (),
+                }
+            }
+            if !matches!(data_type, Some(UNIXFS_RAW) | Some(UNIXFS_FILE)) {
+                return Err(unsupported(anyhow!("block {} is not a UnixFS file", cid)));
+            }
+
+            file.extend_from_slice(data);
+            if file.len() as u64 > max_size {
+                return Err(CarError::TooLarge(file.len() as u64));
+            }
+            for link in &links {
+                append_file(link, blocks, file, max_size, visits, depth + 1)?;
+            }
+        }
+        codec => {
+            return Err(unsupported(anyhow!(
+                "unsupported codec {:#x} in block {}",
+                codec,
+                cid
+            )))
+        }
+    }
+    if file.len() as u64 > max_size {
+        return Err(CarError::TooLarge(file.len() as u64));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_cid::Version;
+
+    fn varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn bytes_field(field: u64, value: &[u8], out: &mut Vec<u8>) {
+        varint(field << 3 | 2, out);
+        varint(value.len() as u64, out);
+        out.extend_from_slice(value);
+    }
+
+    fn cid(version: Version, codec: u64, data: &[u8]) -> Cid {
+        let mut bytes = Vec::new();
+        if version == Version::V1 {
+            varint(1, &mut bytes);
+            varint(codec, &mut bytes);
+        }
+        varint(SHA2_256, &mut bytes);
+        varint(32, &mut bytes);
+        bytes.extend_from_slice(&Sha256::digest(data));
+        Cid::read_bytes(bytes.as_slice()).unwrap()
+    }
+
+    // A dag-pb node holding a UnixFS file with `data` and `links`.
+    fn file_node(data: &[u8], links: &[Cid]) -> Vec<u8> {
+        let mut unixfs = Vec::new();
+        varint(1 << 3, &mut unixfs);
+        varint(UNIXFS_FILE, &mut unixfs);
+        bytes_field(2, data, &mut unixfs);
+
+        let mut node = Vec::new();
+        for link in links {
+            let mut pb_link = Vec::new();
+            bytes_field(1, &link.to_bytes(), &mut pb_link);
+            bytes_field(2, &pb_link, &mut node);
+        }
+        bytes_field(1, &unixfs, &mut node);
+        node
+    }
+
+    fn car(blocks: &[(Cid, Vec<u8>)]) -> Vec<u8> {
+        let mut car = Vec::new();
+        let header = b"header";
+        varint(header.len() as u64, &mut car);
+        car.extend_from_slice(header);
+        for (cid, data) in blocks {
+            let cid = cid.to_bytes();
+            varint((cid.len() + data.len()) as u64, &mut car);
+            car.extend_from_slice(&cid);
+            car.extend_from_slice(data);
+        }
+        car
+    }
+
+    #[test]
+    fn test_single_block_file() {
+        let node = file_node(b"type Thing @entity { id: ID! }", &[]);
+        let root = cid(Version::V0, DAG_PB, &node);
+        let car = car(&[(root, node)]);
+
+        let file = verified_file(root, &car, 1024).unwrap();
+        assert_eq!(file.as_ref(), b"type Thing @entity { id: ID! }");
+    }
+
+    #[test]
+    fn test_multi_block_file() {
+        let first = cid(Version::V1, RAW, b"hello ");
+        let second = cid(Version::V1, RAW, b"world");
+        let node = file_node(b"", &[first, second]);
+        let root = cid(Version::V0, DAG_PB, &node);
+        let car = car(&[
+            (root, node),
+            (first, b"hello ".to_vec()),
+            (second, b"world".to_vec()),
+        ]);
+
+        let file = verified_file(root, &car, 1024).unwrap();
+        assert_eq!(file.as_ref(), b"hello world");
+    }
+
+    #[test]
+    fn test_cid_versions() {
+        // The root is requested as a CIDv1 but in the CAR as a CIDv0, and the other way around
+        // for its link.
+        let leaf = cid(Version::V0, DAG_PB, &file_node(b"world", &[]));
+        let node = file_node(
+            b"hello ",
+            &[cid(Version::V1, DAG_PB, &file_node(b"world", &[]))],
+        );
+        let v0_root = cid(Version::V0, DAG_PB, &node);
+        let v1_root = cid(Version::V1, DAG_PB, &node);
+        let car = car(&[(v0_root, node), (leaf, file_node(b"world", &[]))]);
+
+        let file = verified_file(v1_root, &car, 1024).unwrap();
+        assert_eq!(file.as_ref(), b"hello world");
+    }
+
+    #[test]
+    fn test_identity_block() {
+        // An identity CID holds its data rather than its hash.
+        let mut bytes = Vec::new();
+        for v in [1, RAW, IDENTITY, 5] {
+            varint(v, &mut bytes);
+        }
+        bytes.extend_from_slice(b"world");
+        let leaf = Cid::read_bytes(bytes.as_slice()).unwrap();
+        let node = file_node(b"hello ", &[leaf]);
+        let root = cid(Version::V0, DAG_PB, &node);
+        let car = car(&[(root, node)]);
+
+        let file = verified_file(root, &car, 1024).unwrap();
+        assert_eq!(file.as_ref(), b"hello world");
+    }
+
+    #[test]
+    fn test_unsupported_codec() {
+        const DAG_CBOR: u64 = 0x71;
+        let root = cid(Version::V1, DAG_CBOR, b"\xa0");
+        let car = car(&[(root, b"\xa0".to_vec())]);
+
+        assert!(matches!(
+            verified_file(root, &car, 1024),
+            Err(CarError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_file_too_large() {
+        // Each level links the level below many times, so that a small CAR expands into a file
+        // many times larger than the limit.
+        let leaf_data = vec![b'x'; 100];
+        let leaf = cid(Version::V1, RAW, &leaf_data);
+        let mut blocks = vec![(leaf, leaf_data)];
+        let mut link = leaf;
+        for _ in 0..MAX_DEPTH {
+            let node = file_node(b"", &[link; 16]);
+            link = cid(Version::V0, DAG_PB, &node);
+            blocks.push((link, node));
+        }
+        let car = car(&blocks);
+
+        match verified_file(link, &car, 1024 * 1024) {
+            Err(CarError::TooLarge(size)) => assert!(size <= 1024 * 1024 + 100),
+            _ => panic!("expected the file to be too large"),
+        }
+    }
+
+    #[test]
+    fn test_tampered_block() {
+        let node = file_node(b"hello", &[]);
+        let root = cid(Version::V0, DAG_PB, &node);
+        let car = car(&[(root, file_node(b"jello", &[]))]);
+
+        assert!(matches!(
+            verified_file(root, &car, 1024),
+            Err(CarError::Mismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_missing_block() {
+        let leaf = cid(Version::V1, RAW, b"hello");
+        let node = file_node(b"", &[leaf]);
+        let root = cid(Version::V0, DAG_PB, &node);
+        let car = car(&[(root, node)]);
+
+        assert!(matches!(
+            verified_file(root, &car, 1024),
+            Err(CarError::Mismatch(_))
+        ));
+    }
+}
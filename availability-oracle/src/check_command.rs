@@ -0,0 +1,241 @@
+use crate::arweave::{Arweave, ArweaveImpl};
+use crate::epoch_block_oracle_subgraph::EpochBlockOracleSubgraphImpl;
+use crate::ipfs::{Ipfs, IpfsError, IpfsImpl};
+use crate::local_ipfs::LocalIpfs;
+use crate::{
+    check, fetch_supported_networks, CheckError, Config, FileSizeLimits, Valid, VersionBounds,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use common::prelude::*;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+use tiny_cid::Cid;
+
+#[derive(StructOpt)]
+pub struct CheckCommand {
+    #[structopt(
+        required_unless = "build-dir",
+        help = "The deployment IDs to check. Defaults to the `subgraph.yaml` in --build-dir"
+    )]
+    deployments: Vec<String>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Read the files from a local directory, such as the output of `graph build`, \
+                instead of IPFS"
+    )]
+    build_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        require_delimiter = true,
+        value_delimiter = ",",
+        help = "A comma separated list of the networks to consider supported. \
+                If not set, they are fetched from the epoch block oracle subgraph"
+    )]
+    networks: Vec<String>,
+}
+
+/// Runs `check` for each of the deployments, printing the verdict and every file fetched.
+pub async fn run(logger: &Logger, config: &Config, command: &CheckCommand) -> Result<(), Error> {
+    let mut deployments = command
+        .deployments
+        .iter()
+        .map(|deployment| {
+            Cid::from_str(deployment)
+                .map_err(|e| anyhow!("bad deployment id {}: {}", deployment, e))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let supported_networks = if command.networks.is_empty() {
+        let endpoint = config.epoch_block_oracle_subgraph.clone().ok_or_else(|| {
+            anyhow!("either --networks or --epoch-block-oracle-subgraph is required")
+        })?;
+        fetch_supported_networks(EpochBlockOracleSubgraphImpl::new(logger.clone(), endpoint))
+            .await?
+    } else {
+        command.networks.clone()
+    };
+
+    let arweave = ArweaveImpl::new(
+        config.arweave.clone(),
+        config.arweave_timeout,
+        config.arweave_cache_size,
+    );
+    match &command.build_dir {
+        Some(build_dir) => {
+            let ipfs = LocalIpfs::load(build_dir)?;
+            if deployments.is_empty() {
+                let manifest = ipfs
+                    .manifest()
+                    .ok_or_else(|| anyhow!("no subgraph.yaml found in {}", build_dir.display()))?;
+                deployments.push(manifest);
+            }
+            check_all(
+                &RecordingIpfs::new(ipfs),
+                &arweave,
+                &deployments,
+                &supported_networks,
+                &config.supported_data_source_kinds,
+                &config.supported_features,
+                &config.forbidden_host_fns,
+                &config.file_size_limits,
+                &config.version_bounds,
+            )
+            .await
+        }
+        None => {
+            if config.ipfs.is_empty() {
+                return Err(anyhow!("--ipfs is required to check deployments"));
+            }
+            config.ipfs_policy.check_endpoints(config.ipfs.len())?;
+            let ipfs = IpfsImpl::new(
+                config.ipfs.clone(),
+                config.ipfs_policy,
+                config.ipfs_mode,
+                config.ipfs_retry_policy(),
+                config.ipfs_concurrency,
+                config.ipfs_timeout,
+                config.ipfs_verify_content,
+                config.ipfs_cache_size,
+            );
+            check_all(
+                &RecordingIpfs::new(ipfs),
+                &arweave,
+                &deployments,
+                &supported_networks,
+                &config.supported_data_source_kinds,
+                &config.supported_features,
+                &config.forbidden_host_fns,
+                &config.file_size_limits,
+                &config.version_bounds,
+            )
+            .await
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn check_all<I: Ipfs + Send + Sync>(
+    ipfs: &RecordingIpfs<I>,
+    arweave: &impl Arweave,
+    deployments: &[Cid],
+    supported_networks: &[String],
+    supported_ds_kinds: &[String],
+    supported_features: &[String],
+    forbidden_host_fns: &[String],
+    file_size_limits: &FileSizeLimits,
+    version_bounds: &VersionBounds,
+) -> Result<(), Error> {
+    let mut failed = 0;
+    for deployment in deployments {
+        let validity = match check(
+            ipfs,
+            arweave,
+            *deployment,
+            supported_networks,
+            supported_ds_kinds,
+            supported_features,
+            forbidden_host_fns,
+            file_size_limits,
+            version_bounds,
+        )
+        .await
+        {
+            Ok(()) => Ok(Valid::Yes),
+            Err(CheckError::Invalid(e)) => Ok(Valid::No(e)),
+            Err(CheckError::Other(e)) => Err(e),
+        };
+
+        match &validity {
+            Ok(Valid::Yes) => println!("{}: valid", deployment),
+            Ok(Valid::No(e)) => {
+                failed += 1;
+                println!("{}: invalid ({}): {}", deployment, e.kind(), e);
+            }
+            // Not a verdict, the deployment could not be checked.
+            Err(e) => {
+                failed += 1;
+                println!("{}: check failed: {}", deployment, e);
+            }
+        }
+        for fetch in ipfs.take_fetches() {
+            match fetch.result {
+                Ok(size) => println!(
+                    "  fetched {} ({} bytes) in {} ms",
+                    fetch.cid,
+                    size,
+                    fetch.elapsed.as_millis()
+                ),
+                Err(e) => println!(
+                    "  failed to fetch {} after {} ms: {}",
+                    fetch.cid,
+                    fetch.elapsed.as_millis(),
+                    e
+                ),
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} deployments are invalid or could not be checked",
+            failed,
+            deployments.len()
+        ));
+    }
+    Ok(())
+}
+
+struct Fetch {
+    cid: Cid,
+    // The size of the file, or the error description.
+    result: Result<usize, String>,
+    elapsed: Duration,
+}
+
+/// Wraps an `Ipfs` to record every file requested through it.
+struct RecordingIpfs<I> {
+    inner: I,
+    fetches: Mutex<Vec<Fetch>>,
+}
+
+impl<I> RecordingIpfs<I> {
+    fn new(inner: I) -> Self {
+        RecordingIpfs {
+            inner,
+            fetches: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the fetches recorded since the last call.
+    fn take_fetches(&self) -> Vec<Fetch> {
+        std::mem::take(&mut *self.fetches.lock().unwrap())
+    }
+}
+
+#[async_trait]
+impl<I: Ipfs + Send + Sync> Ipfs for RecordingIpfs<I> {
+    async fn cat(&self, cid: Cid, max_size: u64) -> Result<Bytes, IpfsError> {
+        let start = Instant::now();
+        let res = self.inner.cat(cid, max_size).await;
+        self.fetches.lock().unwrap().push(Fetch {
+            cid,
+            result: match &res {
+                Ok(bytes) => Ok(bytes.len()),
+                Err(e) => Err(e.to_string()),
+            },
+            elapsed: start.elapsed(),
+        });
+        res
+    }
+
+    fn invalidate_cache(&self) {
+        self.inner.invalidate_cache()
+    }
+}
@@ -0,0 +1,165 @@
+//! A safety check on the changes of a reconcile run, so that an outage, such as an IPFS gateway
+//! going down mid-run, doesn't deny a large part of the network in one transaction.
+
+use crate::report::Verdict;
+use structopt::StructOpt;
+
+/// Limits on the deployments a run may newly deny. A run that exceeds them has its new denials
+/// held back, unless forced, while its un-denials are still submitted.
+#[derive(StructOpt, Clone, Copy, Debug, Default)]
+pub struct CircuitBreaker {
+    #[structopt(
+        long,
+        env = "ORACLE_MAX_NEW_DENIALS",
+        help = "Hold back the new denials of a run that would newly deny more than this many deployments"
+    )]
+    pub max_new_denials: Option<usize>,
+
+    #[structopt(
+        long,
+        env = "ORACLE_MAX_NEW_DENIALS_PERCENT",
+        help = "Hold back the new denials of a run that would newly deny more than this percentage of the deployments checked, not counting valid deployment cache hits"
+    )]
+    pub max_new_denials_percent: Option<f64>,
+
+    #[structopt(
+        long,
+        env = "ORACLE_MAX_UNAVAILABLE_DENIALS",
+        help = "Hold back the new denials of a run that would newly deny more than this many deployments for unavailable files, if that is most of the new denials, as it suggests an IPFS outage"
+    )]
+    pub max_unavailable_denials: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "Submit the new denials of a run even if they exceed the circuit breaker limits"
+    )]
+    pub force: bool,
+}
+
+impl CircuitBreaker {
+    /// Returns why the new denials in `verdicts` should not be submitted, if they exceed a limit.
+    /// Ignores `force`, which is up to the caller.
+    pub fn trip(&self, verdicts: &[Verdict]) -> Option<String> {
+        let new_denials: Vec<&Verdict> = verdicts
+            .iter()
+            .filter(|verdict| verdict.deny && !verdict.previous_deny)
+            .collect();
+        let unavailable = new_denials
+            .iter()
+            .filter(|verdict| verdict.unavailable)
+            .count();
+
+        if let Some(max) = self.max_new_denials {
+            if new_denials.len() > max {
+                return Some(format!(
+                    "{} new denials, more than the maximum of {}",
+                    new_denials.len(),
+                    max
+                ));
+            }
+        }
+        if let Some(max_percent) = self.max_new_denials_percent {
+            // Cache hits weren't checked, counting them would water down a mass denial.
+            let checked = verdicts.iter().filter(|verdict| !verdict.cached).count();
+            let percent = 100.0 * new_denials.len() as f64 / checked.max(1) as f64;
+            if percent > max_percent {
+                return Some(format!(
+                    "{} new denials, {:.1}% of the {} checked deployments, more than the maximum of {}%",
+                    new_denials.len(),
+                    percent,
+                    checked,
+                    max_percent
+                ));
+            }
+        }
+        if let Some(max) = self.max_unavailable_denials {
+            if unavailable > max && 2 * unavailable > new_denials.len() {
+                return Some(format!(
+                    "{} of {} new denials are for unavailable files, more than the maximum of {}",
+                    unavailable,
+                    new_denials.len(),
+                    max
+                ));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verdict(previous_deny: bool, invalid_kind: Option<&'static str>) -> Verdict {
+        Verdict {
+            id: [0; 32],
+            cid: String::new(),
+            signal_amount: 0,
+            previous_deny,
+            valid: invalid_kind.is_none(),
+            deny: invalid_kind.is_some(),
+            invalid_kind,
+            reason: None,
+            unavailable: invalid_kind == Some("Unavailable"),
+            cached: false,
+            held_back: false,
+            block_number: 0,
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let verdicts = vec![
+            verdict(false, None),
+            verdict(false, None),
+            // Already denied, not a new denial.
+            verdict(true, Some("Unavailable")),
+            verdict(false, Some("Unavailable")),
+            // A graft base with an unavailable file counts as unavailable.
+            Verdict {
+                unavailable: true,
+                ..verdict(false, Some("BadGraftBase"))
+            },
+            verdict(false, Some("ManifestParseError")),
+        ];
+
+        assert!(CircuitBreaker::default().trip(&verdicts).is_none());
+
+        let breaker =
+            |max_new_denials, max_new_denials_percent, max_unavailable_denials| CircuitBreaker {
+                max_new_denials,
+                max_new_denials_percent,
+                max_unavailable_denials,
+                force: false,
+            };
+        assert!(breaker(Some(3), None, None).trip(&verdicts).is_none());
+        assert!(breaker(Some(2), None, None).trip(&verdicts).is_some());
+        assert!(breaker(None, Some(50.0), None).trip(&verdicts).is_none());
+        assert!(breaker(None, Some(40.0), None).trip(&verdicts).is_some());
+        assert!(breaker(None, None, Some(2)).trip(&verdicts).is_none());
+        assert!(breaker(None, None, Some(1)).trip(&verdicts).is_some());
+    }
+
+    // Cache hits don't count towards the percentage, or they would hide a mass denial.
+    #[test]
+    fn test_circuit_breaker_cached() {
+        let mut verdicts = vec![
+            verdict(false, Some("ManifestParseError")),
+            verdict(false, Some("ManifestParseError")),
+            verdict(false, None),
+        ];
+        verdicts.extend((0..17).map(|_| Verdict {
+            cached: true,
+            ..verdict(false, None)
+        }));
+
+        let breaker = CircuitBreaker {
+            max_new_denials_percent: Some(50.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            breaker.trip(&verdicts).unwrap(),
+            "2 new denials, 66.7% of the 3 checked deployments, more than the maximum of 50%"
+        );
+    }
+}
@@ -20,6 +20,36 @@ pub trait StateManager {
     async fn deny_many(&self, denied_status: Vec<([u8; 32], bool)>) -> Result<(), Error>;
 }
 
+/// The head of the chain the protocol contracts, and so the network subgraph, are on.
+#[async_trait]
+pub trait ChainHead {
+    async fn block_number(&self) -> Result<u64, Error>;
+}
+
+pub struct RpcChainHead {
+    provider: Provider<Http>,
+}
+
+impl RpcChainHead {
+    pub fn new(url: Url) -> Self {
+        let http_client = reqwest::ClientBuilder::new()
+            .tcp_nodelay(true)
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+        Self {
+            provider: Provider::new(Http::new_with_client(url, http_client)),
+        }
+    }
+}
+
+#[async_trait]
+impl ChainHead for RpcChainHead {
+    async fn block_number(&self) -> Result<u64, Error> {
+        Ok(self.provider.get_block_number().await?.as_u64())
+    }
+}
+
 abigen!(RewardsManagerABI, "src/abi/RewardsManager.abi.json");
 abigen!(
     SubgraphAvailabilityManagerABI,
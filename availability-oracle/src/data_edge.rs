@@ -1,4 +1,5 @@
 use crate::graph_monitoring_subgraph::{GraphMonitoringSubgraph, OracleConfig};
+use crate::{FileSizeLimits, VersionBounds};
 use common::prelude::*;
 use ethers::abi::Address;
 
@@ -52,6 +53,8 @@ pub struct OracleConfigParams<'a> {
     pub period: Duration,
     pub grace_period: u64,
     pub supported_data_source_kinds: &'a [String],
+    pub file_size_limits: FileSizeLimits,
+    pub version_bounds: &'a VersionBounds,
     pub network_subgraph_url: &'a str,
     pub epoch_block_oracle_subgraph_url: &'a str,
     pub subgraph_availability_manager_contract: Option<Address>,
@@ -83,17 +86,28 @@ pub fn build_oracle_config(params: &OracleConfigParams) -> Result<OracleConfig,
             .oracle_index
             .map(|i| i.to_string())
             .unwrap_or_default(),
+        max_file_sizes: params.file_size_limits.to_string(),
+        version_bounds: params.version_bounds.to_string(),
     })
 }
 
-/// Checks the local config against the subgraph to determine if it has changed.
+/// Checks the local config against the subgraph to determine if it has changed. The settings
+/// the subgraph doesn't store are checked against `posted_config`, the config this oracle last
+/// posted, and count as changed if it is unknown.
 pub async fn check_config_status(
     local_config: &OracleConfig,
     monitoring_subgraph: &impl GraphMonitoringSubgraph,
     oracle_index: u64,
+    posted_config: Option<&OracleConfig>,
 ) -> ConfigStatus {
     match monitoring_subgraph.fetch_oracle_config(oracle_index).await {
-        Ok(Some(current_config)) => {
+        Ok(Some(mut current_config)) => {
+            current_config.max_file_sizes = posted_config
+                .map(|posted| posted.max_file_sizes.clone())
+                .unwrap_or_default();
+            current_config.version_bounds = posted_config
+                .map(|posted| posted.version_bounds.clone())
+                .unwrap_or_default();
             if *local_config == current_config {
                 ConfigStatus::Unchanged
             } else {
@@ -145,8 +159,16 @@ impl DataEdgeContract {
         local_config: &OracleConfig,
         monitoring_subgraph: &impl GraphMonitoringSubgraph,
         oracle_index: u64,
+        posted_config: Option<&OracleConfig>,
     ) -> Result<bool, Error> {
-        match check_config_status(local_config, monitoring_subgraph, oracle_index).await {
+        match check_config_status(
+            local_config,
+            monitoring_subgraph,
+            oracle_index,
+            posted_config,
+        )
+        .await
+        {
             ConfigStatus::Unchanged => {
                 info!(self.logger, "Config unchanged, skipping DataEdge post";
                     "oracle_index" => oracle_index
@@ -192,6 +214,8 @@ impl DataEdgeContract {
                 "epoch_block_oracle_subgraph_deployment_id": &config.epoch_block_oracle_subgraph_deployment_id,
                 "subgraph_availability_manager_contract": &config.subgraph_availability_manager_contract,
                 "oracle_index": &config.oracle_index,
+                "max_file_sizes": &config.max_file_sizes,
+                "version_bounds": &config.version_bounds,
             }
         });
 
@@ -244,9 +268,10 @@ pub async fn log_dry_run_config(
     local_config: &OracleConfig,
     monitoring_subgraph: Option<&impl GraphMonitoringSubgraph>,
     oracle_index: Option<u64>,
+    posted_config: Option<&OracleConfig>,
 ) {
     if let (Some(subgraph), Some(oracle_index)) = (monitoring_subgraph, oracle_index) {
-        match check_config_status(local_config, subgraph, oracle_index).await {
+        match check_config_status(local_config, subgraph, oracle_index, posted_config).await {
             ConfigStatus::Unchanged => {
                 info!(logger, "Config unchanged, would skip DataEdge post (dry-run)";
                     "oracle_index" => oracle_index
@@ -283,6 +308,8 @@ pub async fn log_dry_run_config(
         "epoch_block_oracle_subgraph_deployment_id" => &local_config.epoch_block_oracle_subgraph_deployment_id,
         "subgraph_availability_manager_contract" => &local_config.subgraph_availability_manager_contract,
         "oracle_index" => &local_config.oracle_index,
+        "max_file_sizes" => &local_config.max_file_sizes,
+        "version_bounds" => &local_config.version_bounds,
     );
 }
 
@@ -304,6 +331,8 @@ mod tests {
             epoch_block_oracle_subgraph_deployment_id: "Qm456".to_string(),
             subgraph_availability_manager_contract: "0x123".to_string(),
             oracle_index: "0".to_string(),
+            max_file_sizes: "manifest:1024,schema:1024,abi:1024,wasm:2048".to_string(),
+            version_bounds: "spec:0.0.2-1.3.0,api:0.0.2-0.0.9".to_string(),
         }
     }
 
@@ -360,7 +389,7 @@ mod tests {
         let config = test_config();
         let mock = MockSubgraphUnchanged(config.clone());
 
-        let status = check_config_status(&config, &mock, 0).await;
+        let status = check_config_status(&config, &mock, 0, Some(&config)).await;
         assert!(matches!(status, ConfigStatus::Unchanged));
     }
 
@@ -372,7 +401,7 @@ mod tests {
         remote_config.min_signal = "200".to_string();
         let mock = MockSubgraphChanged(remote_config);
 
-        let status = check_config_status(&local_config, &mock, 0).await;
+        let status = check_config_status(&local_config, &mock, 0, Some(&local_config)).await;
         match status {
             ConfigStatus::Changed(fields) => {
                 assert!(fields.contains(&"version"));
@@ -383,12 +412,43 @@ mod tests {
         }
     }
 
+    // The subgraph doesn't store the file size limits and version bounds, they are compared to the
+    // posted config.
+    #[tokio::test]
+    async fn test_check_config_status_posted_config() {
+        let config = test_config();
+        let mut remote_config = test_config();
+        remote_config.max_file_sizes = String::new();
+        remote_config.version_bounds = String::new();
+        let mock = MockSubgraphChanged(remote_config);
+
+        let status = check_config_status(&config, &mock, 0, Some(&config)).await;
+        assert!(matches!(status, ConfigStatus::Unchanged));
+
+        let mut posted_config = test_config();
+        posted_config.max_file_sizes = "manifest:1024,schema:1024,abi:1024,wasm:1024".to_string();
+        let status = check_config_status(&config, &mock, 0, Some(&posted_config)).await;
+        assert!(matches!(status, ConfigStatus::Changed(fields) if fields == ["max_file_sizes"]));
+
+        let mut posted_config = test_config();
+        posted_config.version_bounds = "spec:0.0.2-1.2.0,api:0.0.2-0.0.9".to_string();
+        let status = check_config_status(&config, &mock, 0, Some(&posted_config)).await;
+        assert!(matches!(status, ConfigStatus::Changed(fields) if fields == ["version_bounds"]));
+
+        // Without a posted config, whether they changed is unknown.
+        let status = check_config_status(&config, &mock, 0, None).await;
+        assert!(matches!(
+            status,
+            ConfigStatus::Changed(fields) if fields == ["max_file_sizes", "version_bounds"]
+        ));
+    }
+
     #[tokio::test]
     async fn test_check_config_status_not_found() {
         let config = test_config();
         let mock = MockSubgraphNotFound;
 
-        let status = check_config_status(&config, &mock, 0).await;
+        let status = check_config_status(&config, &mock, 0, Some(&config)).await;
         assert!(matches!(status, ConfigStatus::NotFound));
     }
 
@@ -397,7 +457,7 @@ mod tests {
         let config = test_config();
         let mock = MockSubgraphError;
 
-        let status = check_config_status(&config, &mock, 0).await;
+        let status = check_config_status(&config, &mock, 0, Some(&config)).await;
         match status {
             ConfigStatus::FetchError(e) => {
                 assert!(e.to_string().contains("Mock fetch error"));
@@ -6,7 +6,7 @@ use std::collections::BTreeMap;
 use std::time::Duration;
 
 /// Represents the oracle configuration as stored in the graph-monitoring subgraph.
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OracleConfig {
     pub version: String,
@@ -20,6 +20,12 @@ pub struct OracleConfig {
     pub epoch_block_oracle_subgraph_deployment_id: String,
     pub subgraph_availability_manager_contract: String,
     pub oracle_index: String,
+    // Not stored by the subgraph, so never fetched, see `check_config_status`.
+    #[serde(default)]
+    pub max_file_sizes: String,
+    // Not stored by the subgraph either.
+    #[serde(default)]
+    pub version_bounds: String,
 }
 
 impl OracleConfig {
@@ -63,6 +69,12 @@ impl OracleConfig {
         if self.oracle_index != other.oracle_index {
             changed.push("oracle_index");
         }
+        if self.max_file_sizes != other.max_file_sizes {
+            changed.push("max_file_sizes");
+        }
+        if self.version_bounds != other.version_bounds {
+            changed.push("version_bounds");
+        }
         changed
     }
 }
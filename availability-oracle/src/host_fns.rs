@@ -0,0 +1,113 @@
+//! The host functions graph-node provides to WASM mappings, so that a mapping importing a
+//! function graph-node doesn't know, or doesn't provide for its `apiVersion`, can be rejected.
+
+use semver::Version;
+
+/// Host functions by name, and the mapping `apiVersion` that introduced them.
+const HOST_FNS: &[(&str, (u64, u64, u64))] = &[
+    ("abort", (0, 0, 1)),
+    ("log.log", (0, 0, 1)),
+    // Store
+    ("store.get", (0, 0, 1)),
+    ("store.set", (0, 0, 1)),
+    ("store.remove", (0, 0, 1)),
+    ("store.get_in_block", (0, 0, 7)),
+    ("store.loadRelated", (0, 0, 7)),
+    // Ethereum
+    ("ethereum.call", (0, 0, 1)),
+    ("ethereum.encode", (0, 0, 1)),
+    ("ethereum.decode", (0, 0, 1)),
+    ("ethereum.getBalance", (0, 0, 9)),
+    ("ethereum.hasCode", (0, 0, 9)),
+    // Data sources
+    ("dataSource.create", (0, 0, 1)),
+    ("dataSource.createWithContext", (0, 0, 1)),
+    ("dataSource.address", (0, 0, 1)),
+    ("dataSource.network", (0, 0, 1)),
+    ("dataSource.context", (0, 0, 1)),
+    // Conversions
+    ("typeConversion.bytesToString", (0, 0, 1)),
+    ("typeConversion.bytesToHex", (0, 0, 1)),
+    ("typeConversion.bigIntToString", (0, 0, 1)),
+    ("typeConversion.bigIntToHex", (0, 0, 1)),
+    ("typeConversion.stringToH160", (0, 0, 1)),
+    ("typeConversion.bytesToBase58", (0, 0, 1)),
+    ("json.fromBytes", (0, 0, 1)),
+    ("json.try_fromBytes", (0, 0, 1)),
+    ("json.toI64", (0, 0, 1)),
+    ("json.toU64", (0, 0, 1)),
+    ("json.toF64", (0, 0, 1)),
+    ("json.toBigInt", (0, 0, 1)),
+    ("yaml.fromBytes", (0, 0, 9)),
+    ("yaml.try_fromBytes", (0, 0, 9)),
+    ("crypto.keccak256", (0, 0, 1)),
+    // Arithmetic
+    ("bigInt.plus", (0, 0, 1)),
+    ("bigInt.minus", (0, 0, 1)),
+    ("bigInt.times", (0, 0, 1)),
+    ("bigInt.dividedBy", (0, 0, 1)),
+    ("bigInt.dividedByDecimal", (0, 0, 1)),
+    ("bigInt.mod", (0, 0, 1)),
+    ("bigInt.pow", (0, 0, 1)),
+    ("bigInt.fromString", (0, 0, 1)),
+    ("bigInt.bitOr", (0, 0, 1)),
+    ("bigInt.bitAnd", (0, 0, 1)),
+    ("bigInt.leftShift", (0, 0, 1)),
+    ("bigInt.rightShift", (0, 0, 1)),
+    ("bigDecimal.toString", (0, 0, 1)),
+    ("bigDecimal.fromString", (0, 0, 1)),
+    ("bigDecimal.plus", (0, 0, 1)),
+    ("bigDecimal.minus", (0, 0, 1)),
+    ("bigDecimal.times", (0, 0, 1)),
+    ("bigDecimal.dividedBy", (0, 0, 1)),
+    ("bigDecimal.equals", (0, 0, 1)),
+    // External data
+    ("ens.nameByHash", (0, 0, 1)),
+    ("ipfs.cat", (0, 0, 1)),
+    ("ipfs.map", (0, 0, 1)),
+    ("ipfs.getBlock", (0, 0, 1)),
+    ("arweave.transactionData", (0, 0, 1)),
+    ("box.profile", (0, 0, 1)),
+];
+
+/// Whether graph-node provides `function` to mappings of `api_version`.
+pub fn is_provided(function: &str, api_version: &Version) -> bool {
+    HOST_FNS
+        .iter()
+        .find(|(name, _)| *name == function)
+        .is_some_and(|(_, (major, minor, patch))| {
+            *api_version >= Version::new(*major, *minor, *patch)
+        })
+}
+
+/// Whether `function` matches any of the `forbidden` patterns, which are either a function name
+/// or a prefix followed by `*`, such as `ipfs.*`.
+pub fn is_forbidden(function: &str, forbidden: &[String]) -> bool {
+    forbidden
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => function.starts_with(prefix),
+            None => function == pattern,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_fns() {
+        let v4 = Version::new(0, 0, 4);
+        assert!(is_provided("ethereum.call", &v4));
+        assert!(is_provided("abort", &v4));
+        assert!(!is_provided("ethereum.getBalance", &v4));
+        assert!(is_provided("ethereum.getBalance", &Version::new(0, 0, 9)));
+        assert!(!is_provided("ethereum.unknown", &Version::new(0, 0, 9)));
+
+        let forbidden = ["ipfs.*".to_string(), "ens.nameByHash".to_string()];
+        assert!(is_forbidden("ipfs.cat", &forbidden));
+        assert!(is_forbidden("ens.nameByHash", &forbidden));
+        assert!(!is_forbidden("ens.nameByHashes", &forbidden));
+        assert!(!is_forbidden("store.get", &forbidden));
+    }
+}
@@ -0,0 +1,140 @@
+//! Hysteresis for the deny flag, so that a transient failure such as an IPFS outage doesn't deny
+//! a deployment, and a single lucky check doesn't un-deny it.
+
+use crate::parse_secs;
+use std::time::{Duration, SystemTime};
+use structopt::StructOpt;
+
+/// The check results of a deployment over consecutive reconcile runs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeploymentHistory {
+    pub consecutive_failures: u32,
+    pub consecutive_successes: u32,
+
+    /// When the current streak of failures started.
+    pub failing_since: Option<SystemTime>,
+}
+
+impl DeploymentHistory {
+    /// Records the result of checking the deployment in a run.
+    pub fn record(&mut self, valid: bool, now: SystemTime) {
+        if valid {
+            self.consecutive_successes += 1;
+            self.consecutive_failures = 0;
+            self.failing_since = None;
+        } else {
+            self.consecutive_failures += 1;
+            self.consecutive_successes = 0;
+            self.failing_since.get_or_insert(now);
+        }
+    }
+}
+
+/// How many check results it takes to flip the deny flag of a deployment.
+#[derive(StructOpt, Clone, Copy, Debug)]
+pub struct Hysteresis {
+    #[structopt(
+        long,
+        env = "ORACLE_DENY_AFTER_RUNS",
+        default_value = "1",
+        help = "Deny a deployment only once it has failed the checks in this many consecutive runs"
+    )]
+    pub deny_after_runs: u32,
+
+    #[structopt(
+        long = "deny-after-secs",
+        env = "ORACLE_DENY_AFTER_SECS",
+        default_value = "0",
+        parse(try_from_str = parse_secs),
+        help = "Also deny a deployment once it has been failing the checks for this many seconds, \
+                however many runs that took. With the default value of 0 only --deny-after-runs applies"
+    )]
+    pub deny_after: Duration,
+
+    #[structopt(
+        long,
+        env = "ORACLE_UNDENY_AFTER_RUNS",
+        default_value = "1",
+        help = "Un-deny a denied deployment only once it has passed the checks in this many consecutive runs"
+    )]
+    pub undeny_after_runs: u32,
+}
+
+impl Hysteresis {
+    /// The deny flag of a deployment after this run, given the flag before it and the history
+    /// including this run.
+    pub fn deny(&self, previous_deny: bool, history: &DeploymentHistory, now: SystemTime) -> bool {
+        if previous_deny {
+            return history.consecutive_successes < self.undeny_after_runs.max(1);
+        }
+        if history.consecutive_failures == 0 {
+            return false;
+        }
+
+        let failing_for = history
+            .failing_since
+            .and_then(|since| now.duration_since(since).ok())
+            .unwrap_or_default();
+        history.consecutive_failures >= self.deny_after_runs.max(1)
+            || (!self.deny_after.is_zero() && failing_for >= self.deny_after)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn hysteresis() -> Hysteresis {
+        Hysteresis {
+            deny_after_runs: 3,
+            deny_after: 2 * HOUR,
+            undeny_after_runs: 2,
+        }
+    }
+
+    #[test]
+    fn test_deny_after_consecutive_failures() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut history = DeploymentHistory::default();
+
+        history.record(false, start);
+        history.record(false, start);
+        assert!(!hysteresis().deny(false, &history, start));
+
+        // A success resets the streak.
+        history.record(true, start);
+        history.record(false, start);
+        history.record(false, start);
+        assert!(!hysteresis().deny(false, &history, start));
+
+        history.record(false, start);
+        assert!(hysteresis().deny(false, &history, start));
+    }
+
+    #[test]
+    fn test_deny_after_duration() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut history = DeploymentHistory::default();
+
+        history.record(false, start);
+        assert!(!hysteresis().deny(false, &history, start + HOUR));
+
+        history.record(false, start + 2 * HOUR);
+        assert_eq!(history.failing_since, Some(start));
+        assert!(hysteresis().deny(false, &history, start + 2 * HOUR));
+    }
+
+    #[test]
+    fn test_undeny_after_consecutive_successes() {
+        let now = SystemTime::UNIX_EPOCH;
+        let mut history = DeploymentHistory::default();
+
+        history.record(true, now);
+        assert!(hysteresis().deny(true, &history, now));
+
+        history.record(true, now);
+        assert!(!hysteresis().deny(true, &history, now));
+    }
+}
@@ -1,74 +1,501 @@
+use crate::car::{self, CarError};
 use async_trait::async_trait;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use common::prelude::*;
 use common::prometheus;
+use futures::stream::FuturesUnordered;
 use moka::future::Cache;
+use rand::Rng;
 use reqwest::Client;
+use std::str::FromStr;
 use std::time::Duration;
 use tiny_cid::Cid;
+use tokio::time::Instant;
 
 pub enum IpfsError {
-    GatewayTimeout(Cid, Error), // Gateway/Cloudflare timed-out
-    ClientTimeout(Cid, Error),  // Client timed-out when requesting the file
-    NotFound(Cid, Error),       // Manifest not found
+    GatewayTimeout(Cid, Error),  // Gateway/Cloudflare timed-out
+    ClientTimeout(Cid, Error),   // Client timed-out when requesting the file
+    NotFound(Cid, Error),        // Manifest not found
+    ContentMismatch(Cid, Error), // Returned content does not hash to the requested cid
+    Unsupported(Cid, Error),     // Not a readable file, such as a directory or a non-UnixFS codec
+    TooLarge(Cid, u64, u64),     // File is larger than the limit, with the size seen and the limit
     Other(Error),
 }
 
+impl IpfsError {
+    /// Whether the error means that the file is unavailable, rather than a failure to ask for it.
+    /// An endpoint serving content that doesn't match the file doesn't have the file either.
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            IpfsError::GatewayTimeout(..)
+            | IpfsError::ClientTimeout(..)
+            | IpfsError::NotFound(..)
+            | IpfsError::ContentMismatch(..) => true,
+            IpfsError::TooLarge(..) | IpfsError::Unsupported(..) | IpfsError::Other(_) => false,
+        }
+    }
+
+    /// Whether a request that failed with this error is worth repeating against the same endpoint.
+    fn is_retryable(&self) -> bool {
+        match self {
+            // Timeouts and failures such as dropped connections may be transient.
+            IpfsError::GatewayTimeout(..) | IpfsError::ClientTimeout(..) | IpfsError::Other(_) => {
+                true
+            }
+            // The endpoint did answer, asking again won't change the answer.
+            IpfsError::NotFound(..)
+            | IpfsError::ContentMismatch(..)
+            | IpfsError::Unsupported(..)
+            | IpfsError::TooLarge(..) => false,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            IpfsError::GatewayTimeout(..) => "gateway_timeout",
+            IpfsError::ClientTimeout(..) => "client_timeout",
+            IpfsError::NotFound(..) => "not_found",
+            IpfsError::ContentMismatch(..) => "content_mismatch",
+            IpfsError::Unsupported(..) => "unsupported",
+            IpfsError::TooLarge(..) => "too_large",
+            IpfsError::Other(_) => "other",
+        }
+    }
+}
+
+impl std::fmt::Display for IpfsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpfsError::GatewayTimeout(cid, e) => write!(f, "gateway timeout for {}: {}", cid, e),
+            IpfsError::ClientTimeout(cid, e) => write!(f, "client timeout for {}: {}", cid, e),
+            IpfsError::NotFound(cid, e) => write!(f, "not found {}: {}", cid, e),
+            IpfsError::ContentMismatch(cid, e) => {
+                write!(f, "content does not match {}: {}", cid, e)
+            }
+            IpfsError::Unsupported(cid, e) => write!(f, "unsupported content {}: {}", cid, e),
+            IpfsError::TooLarge(cid, size, limit) => write!(
+                f,
+                "file too large: {} ({} bytes, limit {})",
+                cid, size, limit
+            ),
+            IpfsError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
 /// All ipfs interactions required by the oracle.
 #[async_trait]
 pub trait Ipfs {
-    /// Download a file.
-    async fn cat(&self, cid: Cid) -> Result<Bytes, IpfsError>;
+    /// Download a file, failing with `IpfsError::TooLarge` if it's larger than `max_size` bytes.
+    async fn cat(&self, cid: Cid, max_size: u64) -> Result<Bytes, IpfsError>;
 
     /// Invalidate cache of CIDs
     fn invalidate_cache(&self);
 }
 
+/// How `IpfsImpl` uses its endpoints when fetching a file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IpfsPolicy {
+    /// Try the endpoints one after the other, until one of them has the file.
+    Failover,
+    /// Request all endpoints at once, the first one to return the file wins.
+    Race,
+    /// Request all endpoints at once, and only consider a file unavailable if at least this many
+    /// endpoints report it as unavailable.
+    Quorum(usize),
+}
+
+impl FromStr for IpfsPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "failover" => Ok(IpfsPolicy::Failover),
+            "race" => Ok(IpfsPolicy::Race),
+            _ => match s.strip_prefix("quorum:").map(usize::from_str) {
+                Some(Ok(quorum)) if quorum > 0 => Ok(IpfsPolicy::Quorum(quorum)),
+                _ => Err(anyhow!(
+                    "invalid IPFS policy `{}`, expected `failover`, `race` or `quorum:<n>`",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl IpfsPolicy {
+    /// Checks that the policy can be satisfied with `endpoints` endpoints. A quorum larger than
+    /// the number of endpoints could never be reached.
+    pub fn check_endpoints(&self, endpoints: usize) -> Result<(), Error> {
+        match self {
+            IpfsPolicy::Quorum(quorum) if *quorum > endpoints => Err(anyhow!(
+                "IPFS policy `quorum:{}` requires at least {} IPFS endpoints, but {} are configured",
+                quorum,
+                quorum,
+                endpoints
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The API `IpfsImpl` uses to fetch files from its endpoints.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IpfsMode {
+    /// The `/ipfs/<cid>` path of an HTTP gateway.
+    Gateway,
+    /// The `/api/v0` RPC API of a Kubo node.
+    KuboRpc,
+}
+
+impl FromStr for IpfsMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "gateway" => Ok(IpfsMode::Gateway),
+            "kubo-rpc" => Ok(IpfsMode::KuboRpc),
+            _ => Err(anyhow!(
+                "invalid IPFS mode `{}`, expected `gateway` or `kubo-rpc`",
+                s
+            )),
+        }
+    }
+}
+
+/// How `IpfsImpl` retries failed requests.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, for each endpoint.
+    pub retries: usize,
+    /// Delay before the first retry, doubled for each following retry up to `MAX_BACKOFF`.
+    pub backoff: Duration,
+    /// Time allowed to fetch a file over all endpoints and retries, after which it is considered
+    /// unavailable. Unlimited if zero.
+    pub deadline: Duration,
+}
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+impl RetryPolicy {
+    /// The delay before retry number `retry`, counting from 0, randomly shortened by up to half
+    /// so that requests that failed together don't all retry together.
+    fn backoff(&self, retry: usize) -> Duration {
+        let factor = 1u32.checked_shl(retry as u32).unwrap_or(u32::MAX);
+        let delay = self.backoff.saturating_mul(factor).min(MAX_BACKOFF);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
 pub struct IpfsImpl {
-    endpoint: String,
+    endpoints: Vec<String>,
+    policy: IpfsPolicy,
+    mode: IpfsMode,
+    retry: RetryPolicy,
     semaphore: tokio::sync::Semaphore,
     client: Client,
 
-    // Cache for CIDs; we invalidate this cache between runs to ensure we're checking
-    // IPFS regularly
+    // Cache for CIDs, weighed by file size; we invalidate this cache between runs to ensure
+    // we're checking IPFS regularly
     cache: moka::future::Cache<Cid, Bytes>,
 
     // If the request times out, the cid is considered unavailable.
     timeout: Duration,
+
+    // Fetch files as CARs and check that they hash to the requested cid.
+    verify_content: bool,
 }
 
 impl IpfsImpl {
-    pub fn new(endpoint: String, max_concurrent: usize, timeout: Duration) -> Self {
+    /// `cache_size` is the total size, in bytes, of the files kept in the cache.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        endpoints: Vec<String>,
+        policy: IpfsPolicy,
+        mode: IpfsMode,
+        retry: RetryPolicy,
+        max_concurrent: usize,
+        timeout: Duration,
+        verify_content: bool,
+        cache_size: u64,
+    ) -> Self {
         IpfsImpl {
             client: Client::new(),
-            endpoint,
+            endpoints,
+            policy,
+            mode,
+            retry,
             semaphore: tokio::sync::Semaphore::new(max_concurrent),
-            cache: Cache::new(10000),
+            cache: Cache::builder()
+                .weigher(|_, bytes: &Bytes| u32::try_from(bytes.len()).unwrap_or(u32::MAX))
+                .max_capacity(cache_size)
+                .build(),
             timeout,
+            verify_content,
         }
     }
 
-    async fn call(&self, cid: Cid) -> Result<reqwest::Response, IpfsError> {
+    /// The URL of `cid` on `endpoint`. The CID is written in its canonical form, base58 for a
+    /// CIDv0 and base32 for a CIDv1, which gateways and Kubo both accept.
+    fn url(&self, endpoint: &str, cid: Cid) -> String {
+        let endpoint = endpoint.trim_end_matches('/');
+        match (self.mode, self.verify_content) {
+            // Using standard IPFS gateway URL format
+            (IpfsMode::Gateway, false) => format!("{}/ipfs/{}", endpoint, cid),
+            (IpfsMode::Gateway, true) => format!("{}/ipfs/{}?format=car", endpoint, cid),
+            // Kubo is given the same timeout, so that it reports a timeout rather than dropping
+            // the connection.
+            (IpfsMode::KuboRpc, false) => format!(
+                "{}/api/v0/cat?arg={}&timeout={}ms",
+                endpoint,
+                cid,
+                self.timeout.as_millis()
+            ),
+            (IpfsMode::KuboRpc, true) => format!(
+                "{}/api/v0/dag/export?arg={}&timeout={}ms",
+                endpoint,
+                cid,
+                self.timeout.as_millis()
+            ),
+        }
+    }
+
+    async fn call(&self, endpoint: &str, cid: Cid) -> Result<reqwest::Response, IpfsError> {
         let _permit = self.semaphore.acquire().await;
 
-        // Using standard IPFS gateway URL format
-        let url = format!("{}/ipfs/{}", self.endpoint.trim_end_matches('/'), cid);
-        self.client
-            .get(&url)
-            .timeout(self.timeout)
-            .send()
-            .await
-            .map(|res| res.error_for_status())
-            .and_then(|x| x)
+        let url = self.url(endpoint, cid);
+        let req = match (self.mode, self.verify_content) {
+            (IpfsMode::Gateway, false) => self.client.get(url),
+            (IpfsMode::Gateway, true) => self
+                .client
+                .get(url)
+                .header(reqwest::header::ACCEPT, "application/vnd.ipld.car"),
+            (IpfsMode::KuboRpc, _) => self.client.post(url),
+        };
+        let res = req.timeout(self.timeout).send().await.map_err(|e| {
+            if e.is_timeout() {
+                IpfsError::ClientTimeout(cid, e.into())
+            } else {
+                IpfsError::Other(e.into())
+            }
+        })?;
+
+        if self.mode == IpfsMode::KuboRpc && !res.status().is_success() {
+            let status = res.status().as_u16();
+            let body = res.text().await.unwrap_or_default();
+            return Err(kubo_error(cid, status, &body));
+        }
+
+        res.error_for_status()
             .map_err(|e| match e.status().map(|e| e.as_u16()) {
                 Some(GATEWAY_TIMEOUT) | Some(CLOUDFLARE_TIMEOUT) => {
                     IpfsError::GatewayTimeout(cid, e.into())
                 }
-                _ if e.is_timeout() => IpfsError::ClientTimeout(cid, e.into()),
                 Some(NOT_FOUND) => IpfsError::NotFound(cid, e.into()),
                 _ => IpfsError::Other(e.into()),
             })
     }
+
+    /// Download a file from the endpoints, as directed by the policy.
+    async fn fetch_any(
+        &self,
+        cid: Cid,
+        max_size: u64,
+        deadline: Option<Instant>,
+    ) -> Result<Bytes, IpfsError> {
+        let mut errors = Vec::new();
+        match self.policy {
+            IpfsPolicy::Failover => {
+                for endpoint in &self.endpoints {
+                    match self.fetch(endpoint, cid, max_size, deadline).await {
+                        Ok(bytes) => return Ok(bytes),
+                        Err(e) => errors.push(e),
+                    }
+                }
+            }
+            IpfsPolicy::Race | IpfsPolicy::Quorum(_) => {
+                let mut fetches = self
+                    .endpoints
+                    .iter()
+                    .map(|endpoint| self.fetch(endpoint, cid, max_size, deadline))
+                    .collect::<FuturesUnordered<_>>();
+                while let Some(res) = fetches.next().await {
+                    match res {
+                        Ok(bytes) => return Ok(bytes),
+                        Err(e) => errors.push(e),
+                    }
+                }
+            }
+        }
+        Err(combine_errors(self.policy, cid, errors))
+    }
+
+    /// Download a file from a single endpoint, retrying as directed by the retry policy.
+    async fn fetch(
+        &self,
+        endpoint: &str,
+        cid: Cid,
+        max_size: u64,
+        deadline: Option<Instant>,
+    ) -> Result<Bytes, IpfsError> {
+        let mut retry = 0;
+        let res = loop {
+            let e = match self.fetch_once(endpoint, cid, max_size).await {
+                Ok(bytes) => break Ok(bytes),
+                Err(e) => e,
+            };
+            if retry >= self.retry.retries || !e.is_retryable() {
+                break Err(e);
+            }
+            let backoff = self.retry.backoff(retry);
+            // No point in waiting if the deadline will have passed by then.
+            if deadline.is_some_and(|deadline| Instant::now() + backoff >= deadline) {
+                break Err(e);
+            }
+
+            METRICS
+                .ipfs_retries_total
+                .with_label_values(&[endpoint, e.kind()])
+                .inc();
+            tokio::time::sleep(backoff).await;
+            retry += 1;
+        };
+
+        if let Err(e) = &res {
+            METRICS
+                .ipfs_errors_total
+                .with_label_values(&[endpoint, e.kind()])
+                .inc();
+        }
+        res
+    }
+
+    async fn fetch_once(
+        &self,
+        endpoint: &str,
+        cid: Cid,
+        max_size: u64,
+    ) -> Result<Bytes, IpfsError> {
+        METRICS
+            .ipfs_requests_total
+            .with_label_values(&[endpoint])
+            .inc();
+        // A CAR is slightly larger than the file it holds.
+        let body_limit = match self.verify_content {
+            true => max_size.saturating_add(max_size / 100 + CAR_OVERHEAD),
+            false => max_size,
+        };
+        let body = read_body(self.call(endpoint, cid).await?, cid, body_limit).await?;
+        if !self.verify_content {
+            return Ok(body);
+        }
+        match car::verified_file(cid, &body, max_size) {
+            Ok(file) => Ok(file),
+            Err(CarError::TooLarge(size)) => Err(IpfsError::TooLarge(cid, size, max_size)),
+            Err(CarError::Unsupported(e)) => Err(IpfsError::Unsupported(cid, e)),
+            Err(CarError::Mismatch(e)) => {
+                METRICS
+                    .ipfs_content_mismatches_total
+                    .with_label_values(&[endpoint])
+                    .inc();
+                Err(IpfsError::ContentMismatch(cid, e))
+            }
+        }
+    }
+}
+
+// Allowance for the CAR header and the per-block CIDs and framing.
+const CAR_OVERHEAD: u64 = 64 * 1024;
+
+/// Reads the body of a response, giving up as soon as it's known to be larger than `max_size`.
+async fn read_body(
+    mut res: reqwest::Response,
+    cid: Cid,
+    max_size: u64,
+) -> Result<Bytes, IpfsError> {
+    if let Some(len) = res.content_length().filter(|len| *len > max_size) {
+        return Err(IpfsError::TooLarge(cid, len, max_size));
+    }
+
+    let mut body = BytesMut::new();
+    while let Some(chunk) = res.chunk().await.map_err(|e| IpfsError::Other(e.into()))? {
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > max_size {
+            return Err(IpfsError::TooLarge(cid, body.len() as u64, max_size));
+        }
+    }
+    Ok(body.freeze())
+}
+
+/// Maps an error response from the Kubo RPC API, which has a JSON body such as
+/// `{"Message": "context deadline exceeded", "Code": 0, "Type": "error"}`.
+fn kubo_error(cid: Cid, status: u16, body: &str) -> IpfsError {
+    #[derive(serde_derive::Deserialize)]
+    struct KuboError {
+        #[serde(rename = "Message")]
+        message: String,
+    }
+
+    let message = serde_json::from_str::<KuboError>(body)
+        .map(|e| e.message)
+        .unwrap_or_else(|_| body.trim().to_string());
+    let e = anyhow!("kubo rpc error {}: {}", status, message);
+    if message.contains("context deadline exceeded") {
+        IpfsError::GatewayTimeout(cid, e)
+    } else if message.contains("not found") {
+        IpfsError::NotFound(cid, e)
+    } else if KUBO_NOT_A_FILE.iter().any(|m| message.contains(m)) {
+        IpfsError::Unsupported(cid, e)
+    } else {
+        IpfsError::Other(e)
+    }
+}
+
+/// Kubo errors that say the CID resolves to something other than a file it can `cat`, which no
+/// endpoint will answer differently.
+const KUBO_NOT_A_FILE: &[&str] = &[
+    "this dag node is a directory",
+    "not unixfs node",
+    "unsupported node type",
+    "invalid path",
+    "invalid cid",
+];
+
+/// Picks the error to report once every endpoint failed to return a file. The file is only
+/// reported unavailable if enough endpoints agree on it, otherwise the failure is not a verdict
+/// on the file.
+fn combine_errors(policy: IpfsPolicy, cid: Cid, errors: Vec<IpfsError>) -> IpfsError {
+    let required = match policy {
+        IpfsPolicy::Failover | IpfsPolicy::Race => 1,
+        IpfsPolicy::Quorum(quorum) => quorum,
+    };
+    // The file being too large or unsupported is a verdict on its own, no matter what other
+    // endpoints say.
+    if let Some(i) = errors
+        .iter()
+        .position(|e| matches!(e, IpfsError::TooLarge(..) | IpfsError::Unsupported(..)))
+    {
+        return errors.into_iter().nth(i).unwrap();
+    }
+
+    let total = errors.len();
+    let (unavailable, other): (Vec<IpfsError>, Vec<IpfsError>) =
+        errors.into_iter().partition(IpfsError::is_unavailable);
+
+    if unavailable.len() >= required {
+        // Unwrap: `required` is at least 1.
+        return unavailable.into_iter().next().unwrap();
+    }
+    match other.into_iter().next() {
+        Some(e) if required == 1 => e,
+        _ => IpfsError::Other(anyhow!(
+            "only {} of {} IPFS endpoints report {} as unavailable, {} required",
+            unavailable.len(),
+            total,
+            cid,
+            required
+        )),
+    }
 }
 
 const CLOUDFLARE_TIMEOUT: u16 = 524;
@@ -78,33 +505,39 @@ const NOT_FOUND: u16 = 404;
 #[async_trait]
 impl Ipfs for IpfsImpl {
     /// Download a file.
-    async fn cat(&self, cid: Cid) -> Result<Bytes, IpfsError> {
+    async fn cat(&self, cid: Cid, max_size: u64) -> Result<Bytes, IpfsError> {
         if self.cache.contains_key(&cid) {
             METRICS.ipfs_cache_hits.inc();
             let cached_bytes = self.cache.get(&cid).await.unwrap();
+            if cached_bytes.len() as u64 > max_size {
+                return Err(IpfsError::TooLarge(
+                    cid,
+                    cached_bytes.len() as u64,
+                    max_size,
+                ));
+            }
             return Result::Ok(cached_bytes);
         }
 
-        async fn call_with_retry(
-            ipfs: &IpfsImpl,
-            cid: Cid,
-            retries: usize,
-        ) -> Result<reqwest::Response, IpfsError> {
-            let mut last_err = None;
-            for _ in 0..=retries {
-                match ipfs.call(cid).await {
-                    Ok(res) => return Ok(res),
-                    Err(e) => {
-                        last_err = Some(e);
-                    }
+        let final_bytes = if self.retry.deadline.is_zero() {
+            self.fetch_any(cid, max_size, None).await?
+        } else {
+            let deadline = Instant::now() + self.retry.deadline;
+            let fetch = self.fetch_any(cid, max_size, Some(deadline));
+            match tokio::time::timeout_at(deadline, fetch).await {
+                Ok(res) => res?,
+                Err(_) => {
+                    METRICS.ipfs_deadline_exceeded_total.inc();
+                    return Err(IpfsError::ClientTimeout(
+                        cid,
+                        anyhow!(
+                            "not fetched within the deadline of {} s",
+                            self.retry.deadline.as_secs()
+                        ),
+                    ));
                 }
             }
-            Err(last_err.unwrap())
-        }
-
-        let res = call_with_retry(self, cid, 1).await;
-        METRICS.ipfs_requests_total.inc();
-        let final_bytes = res?.bytes().map_err(|e| IpfsError::Other(e.into())).await?;
+        };
 
         self.cache.insert(cid, final_bytes.clone()).await;
         Result::Ok(final_bytes)
@@ -116,7 +549,11 @@ impl Ipfs for IpfsImpl {
 }
 
 struct Metrics {
-    ipfs_requests_total: prometheus::IntCounter,
+    ipfs_requests_total: prometheus::IntCounterVec,
+    ipfs_errors_total: prometheus::IntCounterVec,
+    ipfs_content_mismatches_total: prometheus::IntCounterVec,
+    ipfs_retries_total: prometheus::IntCounterVec,
+    ipfs_deadline_exceeded_total: prometheus::IntCounter,
     ipfs_cache_hits: prometheus::IntCounter,
 }
 
@@ -127,9 +564,33 @@ lazy_static! {
 impl Metrics {
     fn new() -> Self {
         Self {
-            ipfs_requests_total: prometheus::register_int_counter!(
+            ipfs_requests_total: prometheus::register_int_counter_vec!(
                 "ipfs_requests_total",
-                "Total ipfs requests"
+                "Total ipfs requests",
+                &["endpoint"]
+            )
+            .unwrap(),
+            ipfs_errors_total: prometheus::register_int_counter_vec!(
+                "ipfs_errors_total",
+                "Total failed ipfs requests, by error kind",
+                &["endpoint", "kind"]
+            )
+            .unwrap(),
+            ipfs_content_mismatches_total: prometheus::register_int_counter_vec!(
+                "ipfs_content_mismatches_total",
+                "Total files whose content did not hash to the requested cid",
+                &["endpoint"]
+            )
+            .unwrap(),
+            ipfs_retries_total: prometheus::register_int_counter_vec!(
+                "ipfs_retries_total",
+                "Total retried ipfs requests, by the error kind of the failed attempt",
+                &["endpoint", "kind"]
+            )
+            .unwrap(),
+            ipfs_deadline_exceeded_total: prometheus::register_int_counter!(
+                "ipfs_deadline_exceeded_total",
+                "Total files not fetched within the per-file deadline"
             )
             .unwrap(),
             ipfs_cache_hits: prometheus::register_int_counter!(
@@ -140,3 +601,159 @@ impl Metrics {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cid() -> Cid {
+        Cid::from_str("QmWt111111111111111111111111111111111111111111").unwrap()
+    }
+
+    fn not_found() -> IpfsError {
+        IpfsError::NotFound(cid(), anyhow!("not found"))
+    }
+
+    fn other() -> IpfsError {
+        IpfsError::Other(anyhow!("connection refused"))
+    }
+
+    #[test]
+    fn test_ipfs_policy_from_str() {
+        assert_eq!(
+            IpfsPolicy::from_str("failover").unwrap(),
+            IpfsPolicy::Failover
+        );
+        assert_eq!(IpfsPolicy::from_str("race").unwrap(), IpfsPolicy::Race);
+        assert_eq!(
+            IpfsPolicy::from_str("quorum:2").unwrap(),
+            IpfsPolicy::Quorum(2)
+        );
+        assert!(IpfsPolicy::from_str("quorum:0").is_err());
+        assert!(IpfsPolicy::from_str("quorum").is_err());
+
+        assert!(IpfsPolicy::Quorum(2).check_endpoints(2).is_ok());
+        assert!(IpfsPolicy::Quorum(3).check_endpoints(2).is_err());
+        assert!(IpfsPolicy::Race.check_endpoints(1).is_ok());
+    }
+
+    #[test]
+    fn test_kubo_error() {
+        let body = r#"{"Message":"context deadline exceeded","Code":0,"Type":"error"}"#;
+        assert!(matches!(
+            kubo_error(cid(), 500, body),
+            IpfsError::GatewayTimeout(..)
+        ));
+
+        let body = r#"{"Message":"block was not found locally (offline): ipld: could not find QmWt","Code":0,"Type":"error"}"#;
+        assert!(matches!(
+            kubo_error(cid(), 500, body),
+            IpfsError::NotFound(..)
+        ));
+
+        let body = r#"{"Message":"this dag node is a directory","Code":0,"Type":"error"}"#;
+        assert!(matches!(
+            kubo_error(cid(), 500, body),
+            IpfsError::Unsupported(..)
+        ));
+
+        let body = r#"{"Message":"invalid path \"x\": invalid cid","Code":0,"Type":"error"}"#;
+        assert!(matches!(
+            kubo_error(cid(), 500, body),
+            IpfsError::Unsupported(..)
+        ));
+
+        let body = r#"{"Message":"failed to dial peer","Code":0,"Type":"error"}"#;
+        assert!(matches!(kubo_error(cid(), 500, body), IpfsError::Other(..)));
+        assert!(matches!(
+            kubo_error(cid(), 502, "Bad Gateway"),
+            IpfsError::Other(..)
+        ));
+    }
+
+    #[test]
+    fn test_url() {
+        let ipfs = |mode, verify_content| {
+            IpfsImpl::new(
+                vec![],
+                IpfsPolicy::Failover,
+                mode,
+                RetryPolicy {
+                    retries: 0,
+                    backoff: Duration::ZERO,
+                    deadline: Duration::ZERO,
+                },
+                1,
+                Duration::from_secs(30),
+                verify_content,
+                0,
+            )
+        };
+        let v1 = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+        let v1_cid = Cid::from_str(v1).unwrap();
+
+        assert_eq!(
+            ipfs(IpfsMode::Gateway, false).url("https://ipfs.io/", cid()),
+            format!("https://ipfs.io/ipfs/{}", cid())
+        );
+        assert_eq!(
+            ipfs(IpfsMode::Gateway, true).url("https://ipfs.io", v1_cid),
+            format!("https://ipfs.io/ipfs/{}?format=car", v1)
+        );
+        assert_eq!(
+            ipfs(IpfsMode::KuboRpc, false).url("http://localhost:5001", v1_cid),
+            format!(
+                "http://localhost:5001/api/v0/cat?arg={}&timeout=30000ms",
+                v1
+            )
+        );
+    }
+
+    #[test]
+    fn test_combine_errors() {
+        use IpfsPolicy::*;
+
+        // A single unavailability is enough without a quorum.
+        assert!(combine_errors(Failover, cid(), vec![other(), not_found()]).is_unavailable());
+        assert!(combine_errors(Race, cid(), vec![not_found()]).is_unavailable());
+        assert!(!combine_errors(Failover, cid(), vec![other(), other()]).is_unavailable());
+
+        // With a quorum, enough endpoints must agree.
+        assert!(
+            combine_errors(Quorum(2), cid(), vec![not_found(), not_found(), other()])
+                .is_unavailable()
+        );
+        assert!(!combine_errors(Quorum(2), cid(), vec![not_found(), other()]).is_unavailable());
+
+        // No endpoint serving the right content is the same as none having the file.
+        let mismatch = IpfsError::ContentMismatch(cid(), anyhow!("block is missing"));
+        assert!(combine_errors(Failover, cid(), vec![other(), mismatch]).is_unavailable());
+
+        // A file that's too large is never masked.
+        let too_large = IpfsError::TooLarge(cid(), 2048, 1024);
+        assert!(matches!(
+            combine_errors(Quorum(2), cid(), vec![not_found(), too_large]),
+            IpfsError::TooLarge(_, 2048, 1024)
+        ));
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy {
+            retries: 10,
+            backoff: Duration::from_millis(100),
+            deadline: Duration::ZERO,
+        };
+        for (retry, full) in [(0, 100), (1, 200), (3, 800)] {
+            let backoff = policy.backoff(retry);
+            assert!(backoff >= Duration::from_millis(full / 2));
+            assert!(backoff <= Duration::from_millis(full));
+        }
+        // Capped, even when doubling would overflow.
+        assert!(policy.backoff(40) <= MAX_BACKOFF);
+
+        assert!(IpfsError::GatewayTimeout(cid(), anyhow!("timeout")).is_retryable());
+        assert!(other().is_retryable());
+        assert!(!not_found().is_retryable());
+    }
+}
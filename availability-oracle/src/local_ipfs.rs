@@ -0,0 +1,268 @@
+use crate::ipfs::{Ipfs, IpfsError};
+use crate::util::bytes32_to_cid_v0;
+use async_trait::async_trait;
+use bytes::Bytes;
+use common::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use tiny_cid::Cid;
+
+const MANIFEST_FILE: &str = "subgraph.yaml";
+
+/// Serves files from a local directory, such as the `build/` output of `graph build`, so that
+/// a subgraph can be checked before it is deployed.
+///
+/// Files named after a CID are served under that CID. Any other file is served under a CID
+/// derived from the sha256 of its contents, which is not the CID it would get on IPFS. If the
+/// directory has a `subgraph.yaml`, the relative paths in it are rewritten to links to those
+/// CIDs, and the rewritten manifest is available through `manifest`.
+pub struct LocalIpfs {
+    files: HashMap<Cid, Bytes>,
+    manifest: Option<Cid>,
+}
+
+impl LocalIpfs {
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let mut files = HashMap::new();
+        let mut paths = HashMap::new();
+        read_dir(dir, dir, &mut files, &mut paths)
+            .with_context(|| format!("failed to read {}", dir.display()))?;
+
+        let manifest = match paths.get(MANIFEST_FILE).and_then(|cid| files.get(cid)) {
+            Some(raw_manifest) => {
+                let mut manifest: serde_yaml::Value = serde_yaml::from_slice(raw_manifest)
+                    .with_context(|| format!("failed to parse {}", MANIFEST_FILE))?;
+                link_files(&mut manifest, &paths);
+                let raw_manifest = Bytes::from(serde_yaml::to_string(&manifest)?);
+                let cid = content_cid(&raw_manifest);
+                files.insert(cid, raw_manifest);
+                Some(cid)
+            }
+            None => None,
+        };
+
+        Ok(LocalIpfs { files, manifest })
+    }
+
+    /// The CID of the rewritten `subgraph.yaml`, if the directory has one.
+    pub fn manifest(&self) -> Option<Cid> {
+        self.manifest
+    }
+}
+
+fn content_cid(bytes: &[u8]) -> Cid {
+    bytes32_to_cid_v0(Sha256::digest(bytes).into())
+}
+
+fn read_dir(
+    root: &Path,
+    dir: &Path,
+    files: &mut HashMap<Cid, Bytes>,
+    paths: &mut HashMap<String, Cid>,
+) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            read_dir(root, &path, files, paths)?;
+            continue;
+        }
+
+        let bytes = Bytes::from(fs::read(&path)?);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let cid = match Cid::from_str(&name) {
+            Ok(cid) => cid,
+            Err(_) => content_cid(&bytes),
+        };
+
+        // Unwrap: `path` was found by walking `root`.
+        let relative_path = path.strip_prefix(root).unwrap();
+        let relative_path = relative_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        paths.insert(relative_path, cid);
+        files.insert(cid, bytes);
+    }
+    Ok(())
+}
+
+/// Replaces every `file: <relative path>` in the manifest with a link to the file's CID.
+/// Paths that aren't in the directory are left as they are.
+fn link_files(value: &mut serde_yaml::Value, paths: &HashMap<String, Cid>) {
+    use serde_yaml::Value;
+
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                if let (Some("file"), Value::String(path)) = (key.as_str(), &*value) {
+                    if let Some(cid) = paths.get(path.trim_start_matches("./")) {
+                        let mut link = serde_yaml::Mapping::new();
+                        link.insert("/".into(), format!("/ipfs/{}", cid).into());
+                        *value = Value::Mapping(link);
+                    }
+                } else {
+                    link_files(value, paths);
+                }
+            }
+        }
+        Value::Sequence(values) => values.iter_mut().for_each(|v| link_files(v, paths)),
+        _ => (),
+    }
+}
+
+#[async_trait]
+impl Ipfs for LocalIpfs {
+    async fn cat(&self, cid: Cid, max_size: u64) -> Result<Bytes, IpfsError> {
+        let file = self
+            .files
+            .get(&cid)
+            .cloned()
+            .ok_or_else(|| IpfsError::NotFound(cid, anyhow!("not in the local directory")))?;
+        if file.len() as u64 > max_size {
+            return Err(IpfsError::TooLarge(cid, file.len() as u64, max_size));
+        }
+        Ok(file)
+    }
+
+    fn invalidate_cache(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arweave::ArweaveImpl;
+    use crate::{CheckError, FileSizeLimits, Invalid, VersionBounds};
+    use semver::Version;
+    use std::time::Duration;
+
+    const MANIFEST: &str = r#"
+specVersion: 0.0.2
+schema:
+  file: schema.graphql
+dataSources:
+  - kind: ethereum/contract
+    name: Contract
+    network: mainnet
+    source:
+      abi: Contract
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      abis:
+        - name: Contract
+          file: ./Contract/abis/Contract.json
+      entities:
+        - Call
+      eventHandlers:
+        - event: Trigger(uint16)
+          handler: handleTrigger
+      file: Contract/Contract.wasm
+"#;
+
+    #[tokio::test]
+    async fn test_local_build_dir() {
+        let dir = std::env::temp_dir().join(format!(
+            "availability-oracle-local-ipfs-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("Contract/abis")).unwrap();
+        fs::write(dir.join(MANIFEST_FILE), MANIFEST).unwrap();
+        fs::write(
+            dir.join("schema.graphql"),
+            include_bytes!("test_files/schema.graphql"),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Contract/abis/Contract.json"),
+            include_bytes!("test_files/Contract.abi"),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Contract/Contract.wasm"),
+            include_bytes!("test_files/Contract.wasm"),
+        )
+        .unwrap();
+
+        let ipfs = LocalIpfs::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let manifest = ipfs.manifest().unwrap();
+        // The manifest has no Arweave files, so the gateway is never asked.
+        let arweave =
+            ArweaveImpl::new("https://arweave.net".to_string(), Duration::from_secs(1), 0);
+        let supported_ds_kinds = ["ethereum/contract".to_string()];
+        let version_bounds = VersionBounds {
+            min_spec_version: Version::new(0, 0, 2),
+            max_spec_version: Version::new(1, 3, 0),
+            min_api_version: Version::new(0, 0, 2),
+            max_api_version: Version::new(0, 0, 9),
+        };
+        let mut limits = FileSizeLimits {
+            manifest: 1024 * 1024,
+            schema: 1024 * 1024,
+            abi: 1024 * 1024,
+            wasm: 1024 * 1024,
+            file_source: 1024 * 1024,
+        };
+        assert!(crate::check(
+            &ipfs,
+            &arweave,
+            manifest,
+            &["mainnet".to_string()],
+            &supported_ds_kinds,
+            &[],
+            &["ipfs.*".to_string()],
+            &limits,
+            &version_bounds,
+        )
+        .await
+        .is_ok());
+
+        // The mapping is larger than 16 bytes.
+        limits.wasm = 16;
+        match crate::check(
+            &ipfs,
+            &arweave,
+            manifest,
+            &["mainnet".to_string()],
+            &supported_ds_kinds,
+            &[],
+            &["ipfs.*".to_string()],
+            &limits,
+            &version_bounds,
+        )
+        .await
+        {
+            Err(CheckError::Invalid(Invalid::FileTooLarge(_, size, 16))) => assert!(size > 16),
+            _ => panic!("expected the mapping to be too large"),
+        }
+
+        // The mapping imports `bigInt` functions.
+        limits.wasm = 1024 * 1024;
+        match crate::check(
+            &ipfs,
+            &arweave,
+            manifest,
+            &["mainnet".to_string()],
+            &supported_ds_kinds,
+            &[],
+            &["bigInt.*".to_string()],
+            &limits,
+            &version_bounds,
+        )
+        .await
+        {
+            Err(CheckError::Invalid(Invalid::ForbiddenApi(module, function))) => {
+                assert_eq!(module, "index");
+                assert!(function.starts_with("bigInt."));
+            }
+            _ => panic!("expected the mapping to use a forbidden api"),
+        }
+    }
+}
@@ -1,13 +1,31 @@
+mod abi;
+mod arweave;
+mod car;
+mod check_command;
+mod circuit_breaker;
 mod contract;
 mod data_edge;
 mod epoch_block_oracle_subgraph;
 mod graph_monitoring_subgraph;
+mod host_fns;
+mod hysteresis;
 mod ipfs;
+mod local_ipfs;
 mod manifest;
 mod network_subgraph;
+mod proto;
+mod report;
+mod schedule;
+mod schema;
+mod state_store;
+mod substreams;
 mod test;
 mod util;
+mod wasm;
 
+use arweave::{Arweave, ArweaveError, ArweaveImpl};
+use check_command::CheckCommand;
+use circuit_breaker::CircuitBreaker;
 use common::prelude::*;
 use common::prometheus;
 use contract::*;
@@ -17,39 +35,94 @@ use ethers::abi::Address;
 use ethers::signers::LocalWallet;
 use ethers::signers::Signer;
 use graph_monitoring_subgraph::GraphMonitoringSubgraphImpl;
+use hysteresis::Hysteresis;
 use ipfs::*;
-use manifest::{Abi, DataSource, Manifest, Mapping};
+use manifest::{Abi, DataSource, Manifest};
 use network_subgraph::*;
+use report::{FileReportWriter, NullReportWriter, ReportWriter, Verdict};
+use schedule::Schedule;
 use secp256k1::SecretKey;
+use semver::Version;
+use state_store::{JsonStateStore, NullStateStore, OracleState, StateStore};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::SystemTime;
 use std::time::{Duration, Instant};
 use std::{fmt::Display, str::FromStr};
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
 use tiny_cid::Cid;
 use tokio::time::MissedTickBehavior;
 use url::Url;
-use util::bytes32_to_cid_v0;
 
 fn parse_secs(secs: &str) -> Result<Duration, Error> {
     Ok(Duration::from_secs(u64::from_str(secs)?))
 }
 
+fn parse_millis(millis: &str) -> Result<Duration, Error> {
+    Ok(Duration::from_millis(u64::from_str(millis)?))
+}
+
+// Subcommands don't need most of the options, so clap skips the required checks when one is
+// given. Options only needed to reconcile the deny list are `Option`s, checked in `run`.
 #[derive(StructOpt)]
+#[structopt(setting = AppSettings::SubcommandsNegateReqs)]
 struct Config {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
     #[structopt(
         long,
         env = "ORACLE_IPFS",
-        help = "IPFS endpoint with access to the subgraph files"
+        value_delimiter = ",",
+        help = "A comma separated list of IPFS endpoints with access to the subgraph files"
     )]
-    ipfs: String,
+    ipfs: Vec<String>,
+
+    #[structopt(
+        long,
+        env = "ORACLE_IPFS_POLICY",
+        default_value = "failover",
+        help = "How multiple IPFS endpoints are used: `failover` tries them in order, `race` \
+                requests all of them at once, and `quorum:<n>` requests all of them at once but \
+                only considers a file unavailable if at least n endpoints report it as unavailable"
+    )]
+    ipfs_policy: IpfsPolicy,
+
+    #[structopt(
+        long,
+        env = "ORACLE_IPFS_MODE",
+        default_value = "gateway",
+        help = "The API used to fetch files from the IPFS endpoints: `gateway` for the \
+                `/ipfs/<cid>` path of an HTTP gateway, or `kubo-rpc` for the `/api/v0` RPC API \
+                of a Kubo node"
+    )]
+    ipfs_mode: IpfsMode,
+
+    #[structopt(
+        long,
+        env = "ORACLE_IPFS_VERIFY_CONTENT",
+        help = "Fetch files from IPFS as CARs and check that they hash to the requested CID, \
+                so that a misbehaving gateway can't change the verdict"
+    )]
+    ipfs_verify_content: bool,
 
     #[structopt(
         long,
         env = "ORACLE_SUBGRAPH",
         help = "Graphql endpoint to the network subgraph"
     )]
-    subgraph: String,
+    subgraph: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ORACLE_MAX_NETWORK_SUBGRAPH_LAG",
+        default_value = "5000",
+        help = "Refuse to run when the network subgraph is more than this many blocks behind the \
+                chain head of --url"
+    )]
+    max_network_subgraph_lag: u64,
 
     #[structopt(
         long,
@@ -94,6 +167,83 @@ struct Config {
     )]
     ipfs_timeout: Duration,
 
+    #[structopt(
+        long,
+        env = "ORACLE_IPFS_RETRIES",
+        default_value = "1",
+        help = "How many times a failed IPFS request is retried against the same endpoint. \
+                Files that are not found are not retried"
+    )]
+    ipfs_retries: usize,
+
+    #[structopt(
+        long,
+        env = "ORACLE_IPFS_RETRY_BACKOFF_MS",
+        default_value = "500",
+        parse(try_from_str = parse_millis),
+        help = "Delay before the first retry of an IPFS request, in milliseconds. \
+                It is doubled for each following retry, and shortened by a random jitter"
+    )]
+    ipfs_retry_backoff: Duration,
+
+    #[structopt(
+        long,
+        env = "ORACLE_IPFS_DEADLINE_SECS",
+        default_value = "0",
+        parse(try_from_str = parse_secs),
+        help = "Time allowed to fetch a file over all IPFS endpoints and retries, after which \
+                it is considered unavailable. With the default value of 0 there is no deadline"
+    )]
+    ipfs_deadline: Duration,
+
+    #[structopt(
+        long,
+        env = "ORACLE_IPFS_CACHE_SIZE",
+        default_value = "1073741824",
+        help = "Maximum total size, in bytes, of the files kept in the IPFS cache during a run"
+    )]
+    ipfs_cache_size: u64,
+
+    #[structopt(
+        long,
+        env = "ORACLE_ARWEAVE",
+        default_value = "https://arweave.net",
+        help = "Arweave gateway serving the files of `file/arweave` data sources"
+    )]
+    arweave: String,
+
+    #[structopt(
+        long,
+        env = "ORACLE_ARWEAVE_TIMEOUT_SECS",
+        default_value = "30",
+        parse(try_from_str = parse_secs),
+        help = "Arweave timeout after which a file will be considered unavailable"
+    )]
+    arweave_timeout: Duration,
+
+    #[structopt(
+        long,
+        env = "ORACLE_ARWEAVE_CACHE_SIZE",
+        default_value = "1073741824",
+        help = "Maximum total size, in bytes, of the files kept in the Arweave cache during a run"
+    )]
+    arweave_cache_size: u64,
+
+    #[structopt(flatten)]
+    file_size_limits: FileSizeLimits,
+
+    #[structopt(flatten)]
+    version_bounds: VersionBounds,
+
+    #[structopt(flatten)]
+    hysteresis: Hysteresis,
+
+    #[structopt(flatten)]
+    circuit_breaker: CircuitBreaker,
+
+    #[structopt(flatten)]
+    schedule: Schedule,
+
     #[structopt(
         long,
         env = "ORACLE_SIGNING_KEY",
@@ -116,7 +266,7 @@ struct Config {
         env = "EPOCH_BLOCK_ORACLE_SUBGRAPH",
         help = "Graphql endpoint to the epoch block oracle subgraph"
     )]
-    epoch_block_oracle_subgraph: String,
+    epoch_block_oracle_subgraph: Option<String>,
 
     // Note: `ethereum/contract` is a valid alias for `ethereum`
     #[structopt(
@@ -128,6 +278,25 @@ struct Config {
     )]
     supported_data_source_kinds: Vec<String>,
 
+    #[structopt(
+        long,
+        default_value = "ipfs.*",
+        value_delimiter = ",",
+        env = "ORACLE_FORBIDDEN_HOST_FNS",
+        help = "A comma separated list of the host functions mappings may not import. \
+                An entry ending in `*` forbids every function starting with it"
+    )]
+    forbidden_host_fns: Vec<String>,
+
+    #[structopt(
+        long,
+        default_value = "nonFatalErrors,fullTextSearch,grafting,ipfsOnEthereumContracts,aggregations,declaredEthCalls,immutableEntities,bytesAsIds",
+        value_delimiter = ",",
+        env = "ORACLE_SUPPORTED_FEATURES",
+        help = "A comma separated list of the subgraph features to consider supported"
+    )]
+    supported_features: Vec<String>,
+
     #[structopt(
         long,
         env = "SUBGRAPH_AVAILABILITY_MANAGER_CONTRACT",
@@ -143,7 +312,7 @@ struct Config {
     pub rewards_manager_contract: Option<Address>,
 
     #[structopt(long, env = "RPC_URL", help = "RPC url for the network")]
-    pub url: Url,
+    pub url: Option<Url>,
 
     #[structopt(
         long,
@@ -167,6 +336,157 @@ struct Config {
         help = "GraphQL endpoint to the graph-monitoring subgraph for checking current oracle config"
     )]
     pub graph_monitoring_subgraph: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ORACLE_STATE_DIR",
+        help = "Directory where the oracle state, such as the valid deployment cache and the failure history of deployments, is persisted across restarts"
+    )]
+    pub state_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        env = "ORACLE_REPORT_DIR",
+        help = "Directory where a JSON Lines and a CSV report of the verdict for every checked deployment is written after each run"
+    )]
+    pub report_dir: Option<PathBuf>,
+}
+
+/// The maximum size, in bytes, of each kind of file linked from a manifest. Larger files make
+/// the deployment invalid, and are not downloaded further than the limit.
+#[derive(StructOpt, Clone, Copy, Debug)]
+pub struct FileSizeLimits {
+    #[structopt(
+        long = "max-manifest-size",
+        env = "ORACLE_MAX_MANIFEST_SIZE",
+        default_value = "5242880",
+        help = "Maximum size of a subgraph manifest, in bytes"
+    )]
+    pub manifest: u64,
+
+    #[structopt(
+        long = "max-schema-size",
+        env = "ORACLE_MAX_SCHEMA_SIZE",
+        default_value = "5242880",
+        help = "Maximum size of a GraphQL schema, in bytes"
+    )]
+    pub schema: u64,
+
+    #[structopt(
+        long = "max-abi-size",
+        env = "ORACLE_MAX_ABI_SIZE",
+        default_value = "5242880",
+        help = "Maximum size of an ABI, in bytes"
+    )]
+    pub abi: u64,
+
+    #[structopt(
+        long = "max-wasm-size",
+        env = "ORACLE_MAX_WASM_SIZE",
+        default_value = "26214400",
+        help = "Maximum size of a WASM mapping, in bytes"
+    )]
+    pub wasm: u64,
+
+    #[structopt(
+        long = "max-file-source-size",
+        env = "ORACLE_MAX_FILE_SOURCE_SIZE",
+        default_value = "26214400",
+        help = "Maximum size of the file of a file data source, in bytes"
+    )]
+    pub file_source: u64,
+}
+
+impl Display for FileSizeLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "manifest:{},schema:{},abi:{},wasm:{},file_source:{}",
+            self.manifest, self.schema, self.abi, self.wasm, self.file_source
+        )
+    }
+}
+
+/// The manifest `specVersion` and mapping `apiVersion` ranges considered supported, inclusive.
+#[derive(StructOpt, Clone, Debug)]
+pub struct VersionBounds {
+    #[structopt(
+        long,
+        env = "ORACLE_MIN_SPEC_VERSION",
+        default_value = "0.0.2",
+        help = "Minimum supported manifest specVersion"
+    )]
+    pub min_spec_version: Version,
+
+    #[structopt(
+        long,
+        env = "ORACLE_MAX_SPEC_VERSION",
+        default_value = "1.3.0",
+        help = "Maximum supported manifest specVersion"
+    )]
+    pub max_spec_version: Version,
+
+    #[structopt(
+        long,
+        env = "ORACLE_MIN_API_VERSION",
+        default_value = "0.0.2",
+        help = "Minimum supported mapping apiVersion"
+    )]
+    pub min_api_version: Version,
+
+    #[structopt(
+        long,
+        env = "ORACLE_MAX_API_VERSION",
+        default_value = "0.0.9",
+        help = "Maximum supported mapping apiVersion"
+    )]
+    pub max_api_version: Version,
+}
+
+impl VersionBounds {
+    fn spec_version(&self, version: &Version) -> Result<(), Invalid> {
+        match self.min_spec_version <= *version && *version <= self.max_spec_version {
+            true => Ok(()),
+            false => Err(Invalid::UnsupportedSpecVersion(version.clone())),
+        }
+    }
+
+    fn api_version(&self, version: &Version) -> Result<(), Invalid> {
+        match self.min_api_version <= *version && *version <= self.max_api_version {
+            true => Ok(()),
+            false => Err(Invalid::UnsupportedApiVersion(version.clone())),
+        }
+    }
+}
+
+impl Display for VersionBounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "spec:{}-{},api:{}-{}",
+            self.min_spec_version,
+            self.max_spec_version,
+            self.min_api_version,
+            self.max_api_version
+        )
+    }
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Check the given deployments against the configured IPFS endpoint, or a local build
+    /// directory, and print the verdicts, exiting with an error if any of them is invalid.
+    Check(CheckCommand),
+}
+
+impl Config {
+    fn ipfs_retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.ipfs_retries,
+            backoff: self.ipfs_retry_backoff,
+            deadline: self.ipfs_deadline,
+        }
+    }
 }
 
 const VALID_DEPLOYMENT_CACHE_TTL: Duration = Duration::from_secs(60 * 60 * 24);
@@ -177,6 +497,27 @@ async fn main() -> Result<()> {
 }
 
 async fn run(logger: Logger, config: Config) -> Result<()> {
+    if let Some(Command::Check(command)) = &config.command {
+        return check_command::run(&logger, &config, command).await;
+    }
+
+    if config.ipfs.is_empty() {
+        return Err(anyhow!("--ipfs is required"));
+    }
+    config.ipfs_policy.check_endpoints(config.ipfs.len())?;
+    let subgraph_endpoint = config
+        .subgraph
+        .clone()
+        .ok_or_else(|| anyhow!("--subgraph is required"))?;
+    let epoch_block_oracle_subgraph_endpoint = config
+        .epoch_block_oracle_subgraph
+        .clone()
+        .ok_or_else(|| anyhow!("--epoch-block-oracle-subgraph is required"))?;
+    let url = config
+        .url
+        .clone()
+        .ok_or_else(|| anyhow!("--url is required"))?;
+
     let config_params = OracleConfigParams {
         ipfs_concurrency: config.ipfs_concurrency,
         ipfs_timeout: config.ipfs_timeout,
@@ -184,8 +525,10 @@ async fn run(logger: Logger, config: Config) -> Result<()> {
         period: config.period,
         grace_period: config.grace_period,
         supported_data_source_kinds: &config.supported_data_source_kinds,
-        network_subgraph_url: &config.subgraph,
-        epoch_block_oracle_subgraph_url: &config.epoch_block_oracle_subgraph,
+        file_size_limits: config.file_size_limits,
+        version_bounds: &config.version_bounds,
+        network_subgraph_url: &subgraph_endpoint,
+        epoch_block_oracle_subgraph_url: &epoch_block_oracle_subgraph_endpoint,
         subgraph_availability_manager_contract: config.subgraph_availability_manager_contract,
         oracle_index: config.oracle_index,
     };
@@ -202,6 +545,13 @@ async fn run(logger: Logger, config: Config) -> Result<()> {
         )
     };
 
+    let state_store: Box<dyn StateStore> = match &config.state_dir {
+        Some(dir) => Box::new(JsonStateStore::new(dir.clone())),
+        None => Box::new(NullStateStore),
+    };
+    // Valid deployments get checked only every VALID_DEPLOYMENT_CACHE_TTL seconds
+    let mut state = load_state(&logger, &*state_store);
+
     if config.dry_run {
         info!(
             logger,
@@ -218,6 +568,7 @@ async fn run(logger: Logger, config: Config) -> Result<()> {
                 &local_config,
                 monitoring_subgraph.as_ref(),
                 config.oracle_index,
+                state.posted_config.as_ref(),
             )
             .await;
         }
@@ -241,8 +592,8 @@ async fn run(logger: Logger, config: Config) -> Result<()> {
         );
 
         let data_edge = DataEdgeContract::new(
-            &signing_key,
-            config.url.clone(),
+            signing_key,
+            url.clone(),
             config
                 .data_edge_contract
                 .expect("data_edge_contract is required unless dry-run"),
@@ -250,26 +601,44 @@ async fn run(logger: Logger, config: Config) -> Result<()> {
         )
         .await?;
 
-        data_edge
-            .post_config_if_changed(&local_config, &monitoring_subgraph, oracle_index)
+        let posted = data_edge
+            .post_config_if_changed(
+                &local_config,
+                &monitoring_subgraph,
+                oracle_index,
+                state.posted_config.as_ref(),
+            )
             .await?;
+        if posted {
+            state.posted_config = Some(local_config);
+            save_state(&logger, &*state_store, &state);
+        }
     }
 
     let ipfs = IpfsImpl::new(
         config.ipfs.clone(),
+        config.ipfs_policy,
+        config.ipfs_mode,
+        config.ipfs_retry_policy(),
         config.ipfs_concurrency,
         config.ipfs_timeout,
+        config.ipfs_verify_content,
+        config.ipfs_cache_size,
     );
-    let subgraph = NetworkSubgraphImpl::new(logger.clone(), config.subgraph.clone());
-    let epoch_subgraph = EpochBlockOracleSubgraphImpl::new(
-        logger.clone(),
-        config.epoch_block_oracle_subgraph.clone(),
+    let arweave = ArweaveImpl::new(
+        config.arweave.clone(),
+        config.arweave_timeout,
+        config.arweave_cache_size,
     );
+    let subgraph = NetworkSubgraphImpl::new(logger.clone(), subgraph_endpoint);
+    let chain_head = RpcChainHead::new(url.clone());
+    let epoch_subgraph =
+        EpochBlockOracleSubgraphImpl::new(logger.clone(), epoch_block_oracle_subgraph_endpoint);
     let contract: Box<dyn StateManager> = if config.dry_run {
         Box::new(StateManagerDryRun::new(logger.clone()))
     } else {
         state_manager(
-            config.url,
+            url,
             signing_key.as_ref().unwrap(),
             config.rewards_manager_contract,
             config.subgraph_availability_manager_contract,
@@ -279,7 +648,23 @@ async fn run(logger: Logger, config: Config) -> Result<()> {
         .await
         .expect("Configuration error: either [`REWARDS_MANAGER_CONTRACT`] or [`SUBGRAPH_AVAILABILITY_MANAGER_CONTRACT` and `ORACLE_INDEX`] must be provided.")
     };
-    let grace_period = Duration::from_secs(config.grace_period);
+    let settings = RunSettings {
+        min_signal: config.min_signal,
+        max_network_subgraph_lag: config.max_network_subgraph_lag,
+        grace_period: Duration::from_secs(config.grace_period),
+        supported_ds_kinds: config.supported_data_source_kinds.clone(),
+        supported_features: config.supported_features.clone(),
+        forbidden_host_fns: config.forbidden_host_fns.clone(),
+        file_size_limits: config.file_size_limits,
+        version_bounds: config.version_bounds.clone(),
+        hysteresis: config.hysteresis,
+        circuit_breaker: config.circuit_breaker,
+        schedule: config.schedule,
+    };
+    let report_writer: Box<dyn ReportWriter> = match &config.report_dir {
+        Some(dir) => Box::new(FileReportWriter::new(dir.clone())),
+        None => Box::new(NullReportWriter),
+    };
 
     common::metrics::serve(logger.clone(), config.metrics_port);
 
@@ -288,9 +673,6 @@ async fn run(logger: Logger, config: Config) -> Result<()> {
         let mut interval = tokio::time::interval(config.period);
         interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
-        // Valid deployments get checked only every VALID_DEPLOYMENT_CACHE_TTL seconds
-        let mut valid_deployment_cache: Vec<(Cid, SystemTime)> = Vec::new();
-
         loop {
             interval.tick().await;
 
@@ -300,23 +682,27 @@ async fn run(logger: Logger, config: Config) -> Result<()> {
 
             match reconcile_deny_list(
                 &logger,
-                &ipfs,
+                Sources {
+                    ipfs: &ipfs,
+                    arweave: &arweave,
+                    chain_head: &chain_head,
+                    subgraph: subgraph.clone(),
+                    epoch_subgraph: epoch_subgraph.clone(),
+                },
                 &*contract,
-                subgraph.clone(),
-                config.min_signal,
-                grace_period,
-                epoch_subgraph.clone(),
-                &config.supported_data_source_kinds,
-                valid_deployment_cache.clone(),
+                &settings,
+                state.clone(),
+                &*report_writer,
             )
             .await
             {
-                Ok(updated_deployment_cache) => {
+                Ok(updated_state) => {
                     METRICS.reconcile_runs_ok.inc();
-                    valid_deployment_cache = updated_deployment_cache;
+                    state = updated_state;
                     info!(logger, "Deployment cache updated";
-                        "count" => valid_deployment_cache.len()
+                        "count" => state.valid_deployments.len()
                     );
+                    save_state(&logger, &*state_store, &state);
                 }
                 Err(e) => {
                     METRICS.reconcile_runs_err.inc();
@@ -334,23 +720,64 @@ async fn run(logger: Logger, config: Config) -> Result<()> {
             // Invalidate the IPFS cache between runs to ensure that we're checking at least
             // once for every CID per-run
             ipfs.invalidate_cache();
+            arweave.invalidate_cache();
         }
     }
     match reconcile_deny_list(
         &logger,
-        &ipfs,
+        Sources {
+            ipfs: &ipfs,
+            arweave: &arweave,
+            chain_head: &chain_head,
+            subgraph,
+            epoch_subgraph: epoch_subgraph.clone(),
+        },
         &*contract,
-        subgraph,
-        config.min_signal,
-        grace_period,
-        epoch_subgraph.clone(),
-        &config.supported_data_source_kinds,
-        Vec::new(),
+        &settings,
+        state,
+        &*report_writer,
     )
     .await
     {
-        Ok(_) => return Ok(()),
-        Err(e) => return Err(e),
+        Ok(updated_state) => {
+            save_state(&logger, &*state_store, &updated_state);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Restores the oracle state from the state store, dropping expired valid deployment cache
+/// entries. A state that can't be loaded is not fatal, the oracle simply starts out afresh.
+fn load_state(logger: &Logger, state_store: &dyn StateStore) -> OracleState {
+    match state_store.load() {
+        Ok(mut state) => {
+            state.valid_deployments.retain(|(_, last_validated)| {
+                last_validated
+                    .elapsed()
+                    .map(|elapsed| elapsed < VALID_DEPLOYMENT_CACHE_TTL)
+                    .unwrap_or(false)
+            });
+            info!(logger, "Oracle state loaded";
+                "cached_deployments" => state.valid_deployments.len(),
+                "deployment_history" => state.deployment_history.len(),
+            );
+            state
+        }
+        Err(e) => {
+            warn!(logger, "Failed to load oracle state, starting with an empty state";
+                "error" => format!("{:#}", e)
+            );
+            OracleState::default()
+        }
+    }
+}
+
+fn save_state(logger: &Logger, state_store: &dyn StateStore, state: &OracleState) {
+    if let Err(e) = state_store.save(state) {
+        warn!(logger, "Failed to save oracle state";
+            "error" => format!("{:#}", e)
+        );
     }
 }
 
@@ -388,127 +815,393 @@ async fn state_manager(
     None
 }
 
+/// Where a run reads the deployments and their files from.
+pub struct Sources<'a, I, A, S, E> {
+    pub ipfs: &'a I,
+    pub arweave: &'a A,
+    pub chain_head: &'a dyn ChainHead,
+    pub subgraph: Arc<S>,
+    pub epoch_subgraph: Arc<E>,
+}
+
+/// The settings of a run, the same for every run of the oracle.
+pub struct RunSettings {
+    pub min_signal: u64,
+    pub max_network_subgraph_lag: u64,
+    pub grace_period: Duration,
+    pub supported_ds_kinds: Vec<String>,
+    pub supported_features: Vec<String>,
+    pub forbidden_host_fns: Vec<String>,
+    pub file_size_limits: FileSizeLimits,
+    pub version_bounds: VersionBounds,
+    pub hysteresis: Hysteresis,
+    pub circuit_breaker: CircuitBreaker,
+    pub schedule: Schedule,
+}
+
 /// Does the thing that the availablitiy oracle does, namely:
 /// 1. Grab the list of all deployments over the curation threshold from the subgraph.
 /// 2. Check if their availability status changed.
 /// 3. Update the deny list accordingly.
 pub async fn reconcile_deny_list(
     logger: &Logger,
-    ipfs: &impl Ipfs,
+    sources: Sources<
+        '_,
+        impl Ipfs,
+        impl Arweave,
+        impl NetworkSubgraph,
+        impl EpochBlockOracleSubgraph,
+    >,
     state_manager: &dyn contract::StateManager,
-    subgraph: Arc<impl NetworkSubgraph>,
-    min_signal: u64,
-    grace_period: Duration,
-    epoch_subgraph: Arc<impl EpochBlockOracleSubgraph>,
-    supported_ds_kinds: &[String],
-    valid_deployment_cache: Vec<(Cid, SystemTime)>,
-) -> Result<Vec<(Cid, SystemTime)>, Error> {
+    settings: &RunSettings,
+    state: OracleState,
+    report_writer: &dyn ReportWriter,
+) -> Result<OracleState, Error> {
     let logger = logger.clone();
-
-    // Fetch supported networks
-    let mut supported_networks = Vec::new();
-    let networks_stream = epoch_subgraph.supported_networks();
-    futures::pin_mut!(networks_stream);
-    while let Some(network) = networks_stream.next().await {
-        match network {
-            Ok(network_id) => supported_networks.push(network_id),
-            Err(e) => Err(e)?,
-        }
+    let Sources {
+        ipfs,
+        arweave,
+        chain_head,
+        subgraph,
+        epoch_subgraph,
+    } = sources;
+    let RunSettings {
+        min_signal,
+        max_network_subgraph_lag,
+        grace_period,
+        ref supported_ds_kinds,
+        ref supported_features,
+        ref forbidden_host_fns,
+        ref file_size_limits,
+        ref version_bounds,
+        ref hysteresis,
+        ref circuit_breaker,
+        ref schedule,
+    } = *settings;
+    let OracleState {
+        valid_deployments: valid_deployment_cache,
+        mut deployment_history,
+        mut last_checked,
+        left_over,
+        posted_config,
+    } = state;
+    let now = SystemTime::now();
+
+    // The whole run is based on the latest block of the network subgraph, provided that it is
+    // healthy and close enough to the chain head.
+    let meta = subgraph.meta().await?;
+    let chain_head = chain_head.block_number().await?;
+    let lag = chain_head.saturating_sub(meta.block_number);
+    METRICS
+        .network_subgraph_block_number
+        .set(meta.block_number as i64);
+    METRICS.network_subgraph_lag.set(lag as i64);
+    info!(logger, "Network subgraph block";
+        "block" => meta.block_number,
+        "hash" => meta.block_hash.as_deref().unwrap_or_default(),
+        "deployment" => &meta.deployment,
+        "lag" => lag,
+    );
+    if meta.has_indexing_errors {
+        return Err(anyhow!(
+            "the network subgraph {} has indexing errors",
+            meta.deployment
+        ));
+    }
+    if lag > max_network_subgraph_lag {
+        return Err(anyhow!(
+            "the network subgraph is {} blocks behind the chain head, more than the maximum of {}",
+            lag,
+            max_network_subgraph_lag
+        ));
     }
 
+    let supported_networks = fetch_supported_networks(epoch_subgraph).await?;
     info!(logger, "Supported networks";
         "alias" => supported_networks.join(", ")
     );
 
-    // Check the availability status of all subgraphs, and gather which should flip the deny flag.
-    let deployment_status: Vec<([u8; 32], bool, bool, SystemTime)> = subgraph
-        .deployments_over_threshold(min_signal, grace_period)
-        .map(|deployment| async {
-            let deployment = deployment?;
-            let id = bytes32_to_cid_v0(deployment.id);
+    let block_number = meta.block_number;
 
-            // Valid subgraphs are only checked every VALID_DEPLOYMENT_CACHE_TTL seconds to reduce IPFS requests
-            let cached = valid_deployment_cache
-                .iter()
-                .filter(|(_, last_validated)| {
-                    last_validated.elapsed().unwrap() < VALID_DEPLOYMENT_CACHE_TTL
-                })
-                .find(|(cid, _)| *cid == id);
+    let mut deployments: Vec<SubgraphDeployment> = subgraph
+        .deployments_over_threshold(min_signal, grace_period, block_number)
+        .try_collect()
+        .await?;
+    schedule.order(&mut deployments, &left_over, &last_checked);
+
+    // Valid subgraphs are only checked every VALID_DEPLOYMENT_CACHE_TTL seconds to reduce IPFS requests
+    let cached = |id: &Cid| {
+        valid_deployment_cache
+            .iter()
+            .filter(|(_, last_validated)| {
+                last_validated.elapsed().unwrap() < VALID_DEPLOYMENT_CACHE_TTL
+            })
+            .find(|(cid, _)| cid == id)
+            .map(|(_, last_validated)| *last_validated)
+    };
 
-            if cached.is_some() {
+    // Cache hits are free, the other deployments are taken in order until the budget runs out.
+    // Denied deployments are always checked, so that only actual checks count towards un-denying
+    // them.
+    let mut checks_left = schedule.max_checks_per_run.unwrap_or(usize::MAX);
+    let scheduled = deployments.iter().filter_map(|deployment| {
+        let cached = match deployment.deny {
+            true => None,
+            false => cached(&deployment.manifest),
+        };
+        if cached.is_none() {
+            if checks_left == 0 {
+                return None;
+            }
+            checks_left -= 1;
+        }
+        Some((*deployment, cached))
+    });
+    let run_deadline = schedule.max_run_time.map(|max| Instant::now() + max);
+
+    // Check the availability status of the scheduled subgraphs. Checks that would start after the
+    // deadline are skipped, and the deployment left over. The checks complete in any order, so that
+    // a slow deployment doesn't hold up the others, and are put back in schedule order after.
+    let supported_networks = &supported_networks;
+    let mut results: Vec<(usize, Option<_>)> = futures::stream::iter(scheduled.enumerate())
+        .map(|(i, (deployment, cached))| async move {
+            if let Some(last_validated) = cached {
                 METRICS.valid_deployment_cache_hits.inc();
-                return Ok((deployment, Valid::Yes, cached.unwrap().1));
-            } else {
-                let validity = match check(ipfs, id, &supported_networks, supported_ds_kinds).await
-                {
-                    Ok(()) => Valid::Yes,
-                    Err(CheckError::Invalid(e)) => Valid::No(e),
-                    Err(CheckError::Other(e)) => return Err(e),
-                };
-                return Ok((deployment, validity, SystemTime::now()));
+                return Ok((i, Some((deployment, Valid::Yes, last_validated, true))));
+            }
+            if run_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok((i, None));
+            }
+
+            let check = check(
+                ipfs,
+                arweave,
+                deployment.manifest,
+                supported_networks,
+                supported_ds_kinds,
+                supported_features,
+                forbidden_host_fns,
+                file_size_limits,
+                version_bounds,
+            );
+            let res = match schedule.check_timeout.is_zero() {
+                true => check.await,
+                false => match tokio::time::timeout(schedule.check_timeout, check).await {
+                    Ok(res) => res,
+                    Err(_) => {
+                        METRICS.check_timeouts_total.inc();
+                        Err(CheckError::Invalid(Invalid::CheckTimeout(
+                            schedule.check_timeout,
+                        )))
+                    }
+                },
+            };
+            let validity = match res {
+                Ok(()) => Valid::Yes,
+                Err(CheckError::Invalid(e)) => Valid::No(e),
+                Err(CheckError::Other(e)) => return Err(e),
             };
+            Ok((i, Some((deployment, validity, SystemTime::now(), false))))
         })
-        .buffered(100)
-        .try_filter_map(move |(deployment, validity, last_validated)| {
-            let logger = logger.clone();
-            async move {
-                info!(logger, "Check subgraph";
-                                "id" => hex::encode(deployment.id),
-                                "cid" => deployment.ipfs_hash()
-                );
+        .buffer_unordered(schedule.check_concurrency.max(1))
+        .try_collect()
+        .await?;
+    results.sort_unstable_by_key(|(i, _)| *i);
 
-                let should_deny = matches!(validity, Valid::No(_));
-                Ok(match deployment.deny == should_deny {
-                    // The validity is unchanged.
-                    true => {
-                        match validity {
-                            Valid::Yes => (),
-                            // Always print the error reason
-                            Valid::No(_) => {
-                                info!(logger, "Invalid";
-                                    "id" => hex::encode(deployment.id),
-                                    "cid" => deployment.ipfs_hash(),
-                                    "reason" => validity.to_string(),
-                                );
-                            }
-                        };
-                        Some((deployment.id, should_deny, false, last_validated))
-                    }
+    // Gather which deployments should flip the deny flag. The state is keyed by the manifest CID
+    // the network subgraph has, which need not be the v0 CID of the deployment ID.
+    let mut deployment_status: Vec<(Cid, Verdict, SystemTime)> = Vec::new();
+    for (deployment, validity, last_validated, cached) in
+        results.into_iter().filter_map(|(_, result)| result)
+    {
+        info!(logger, "Check subgraph";
+                        "id" => hex::encode(deployment.id),
+                        "cid" => deployment.ipfs_hash()
+        );
 
-                    // The validity status changed, flip the deny flag.
-                    false => {
-                        info!(logger, "Change deny status";
-                                        "id" => hex::encode(deployment.id),
-                                        "cid" => deployment.ipfs_hash(),
-                                        "status" => should_deny,
-                                        "reason" => validity.to_string(),
+        let valid = matches!(validity, Valid::Yes);
+        let history = deployment_history.entry(deployment.manifest).or_default();
+        // A cache hit is not a check, so it leaves the history and the deny flag as they are.
+        let should_deny = match cached {
+            true => deployment.deny,
+            false => {
+                history.record(valid, now);
+                last_checked.insert(deployment.manifest, last_validated);
+                hysteresis.deny(deployment.deny, history, now)
+            }
+        };
+
+        match deployment.deny == should_deny {
+            // The validity is unchanged.
+            true => {
+                match validity {
+                    Valid::Yes => (),
+                    // Always print the error reason
+                    Valid::No(_) => {
+                        info!(logger, "Invalid";
+                            "id" => hex::encode(deployment.id),
+                            "cid" => deployment.ipfs_hash(),
+                            "reason" => validity.to_string(),
+                            "consecutive_failures" => history.consecutive_failures,
                         );
-                        Some((deployment.id, should_deny, true, last_validated))
                     }
-                })
+                };
             }
-        })
-        .try_collect()
-        .await?;
+
+            // The validity status changed, flip the deny flag.
+            false => {
+                info!(logger, "Change deny status";
+                                "id" => hex::encode(deployment.id),
+                                "cid" => deployment.ipfs_hash(),
+                                "status" => should_deny,
+                                "reason" => validity.to_string(),
+                );
+            }
+        };
+
+        let verdict = Verdict {
+            id: deployment.id,
+            cid: deployment.ipfs_hash(),
+            signal_amount: deployment.signal_amount,
+            previous_deny: deployment.deny,
+            valid,
+            deny: should_deny,
+            invalid_kind: match &validity {
+                Valid::Yes => None,
+                Valid::No(e) => Some(e.kind()),
+            },
+            unavailable: match &validity {
+                Valid::Yes => false,
+                Valid::No(e) => e.is_unavailable(),
+            },
+            reason: match &validity {
+                Valid::Yes => None,
+                Valid::No(e) => Some(e.to_string()),
+            },
+            cached,
+            held_back: false,
+            block_number,
+        };
+        deployment_status.push((deployment.manifest, verdict, last_validated));
+    }
+
+    // The deployments that didn't fit in the budget of this run go first in the next one.
+    let processed: HashSet<[u8; 32]> = deployment_status
+        .iter()
+        .map(|(_, verdict, _)| verdict.id)
+        .collect();
+    let left_over: HashSet<Cid> = deployments
+        .iter()
+        .filter(|deployment| !processed.contains(&deployment.id))
+        .map(|deployment| deployment.manifest)
+        .collect();
+    METRICS.left_over_deployments.set(left_over.len() as i64);
+    if !left_over.is_empty() {
+        info!(logger, "Deployments left over for the next run";
+            "count" => left_over.len()
+        );
+    }
+
+    let mut verdicts: Vec<Verdict> = deployment_status
+        .iter()
+        .map(|(_, verdict, _)| verdict.clone())
+        .collect();
+    METRICS.circuit_breaker_aborted_changes.set(0);
+    if let Some(reason) = circuit_breaker.trip(&verdicts) {
+        if circuit_breaker.force {
+            warn!(logger, "Circuit breaker tripped, submitting anyway since forced";
+                "reason" => &reason
+            );
+        } else {
+            // Hold back the new denials, un-denying is still safe.
+            let held_back: Vec<&mut Verdict> = verdicts
+                .iter_mut()
+                .filter(|verdict| verdict.deny && !verdict.previous_deny)
+                .collect();
+            METRICS.circuit_breaker_trips_total.inc();
+            METRICS
+                .circuit_breaker_aborted_changes
+                .set(held_back.len() as i64);
+            warn!(logger, "Circuit breaker tripped, not submitting the new denials of this run";
+                "reason" => &reason,
+                "held_back" => held_back.len(),
+            );
+            for verdict in held_back {
+                verdict.held_back = true;
+                warn!(logger, "Aborted change";
+                    "id" => hex::encode(verdict.id),
+                    "cid" => &verdict.cid,
+                    "deny" => verdict.deny,
+                    "reason" => verdict.reason.as_deref().unwrap_or_default(),
+                );
+            }
+        }
+    }
+
+    // Record the verdicts before submitting, so that they're available even if the submission fails.
+    if let Err(e) = report_writer.write(&verdicts) {
+        warn!(logger, "Failed to write verdict report";
+            "error" => format!("{:#}", e)
+        );
+    }
 
     // Flip on chain status for those deployments that changed
-    let changed_deployments = deployment_status
+    let changed_deployments = verdicts
         .iter()
-        .filter(|(_, _, status_changed, _)| *status_changed)
-        .map(|(cid, should_deny, _, _)| (*cid, *should_deny))
+        .filter(|verdict| verdict.status_changed() && !verdict.held_back)
+        .map(|verdict| (verdict.id, verdict.deny))
         .collect();
     match state_manager.deny_many(changed_deployments).await {
         Ok(_) => {}
         Err(e) => return Err(e),
     };
 
-    // Return updated deployment cache
-    let updated_deployment_cache: Vec<(Cid, SystemTime)> = deployment_status
+    // Return the updated state. Only deployments that passed the checks and are not denied are
+    // cached, those the hysteresis keeps denied must keep being checked. The history is only kept
+    // while it can still matter. Left over deployments keep what they had.
+    let valid_deployments: Vec<(Cid, SystemTime)> = deployment_status
+        .iter()
+        .filter(|(_, verdict, _)| verdict.valid && !verdict.deny)
+        .map(|(cid, _, last_validated)| (*cid, *last_validated))
+        .chain(
+            valid_deployment_cache
+                .iter()
+                .filter(|(cid, _)| left_over.contains(cid))
+                .copied(),
+        )
+        .collect();
+    let deployment_history = deployment_status
         .iter()
-        .filter(|(_, should_deny, _, _)| !*should_deny)
-        .map(|(cid, _, _, last_validated)| (bytes32_to_cid_v0(*cid), *last_validated))
+        .filter(|(_, verdict, _)| verdict.deny || !verdict.valid)
+        .map(|(cid, _, _)| *cid)
+        .chain(left_over.iter().copied())
+        .filter_map(|cid| {
+            deployment_history
+                .remove(&cid)
+                .map(|history| (cid, history))
+        })
         .collect();
-    Ok(updated_deployment_cache)
+    let deployed: HashSet<Cid> = deployments.iter().map(|d| d.manifest).collect();
+    last_checked.retain(|cid, _| deployed.contains(cid));
+    Ok(OracleState {
+        valid_deployments,
+        deployment_history,
+        last_checked,
+        left_over,
+        posted_config,
+    })
+}
+
+async fn fetch_supported_networks(
+    epoch_subgraph: Arc<impl EpochBlockOracleSubgraph>,
+) -> Result<Vec<String>, Error> {
+    let mut supported_networks = Vec::new();
+    let networks_stream = epoch_subgraph.supported_networks();
+    futures::pin_mut!(networks_stream);
+    while let Some(network) = networks_stream.next().await {
+        supported_networks.push(network?);
+    }
+    Ok(supported_networks)
 }
 
 enum Valid {
@@ -525,18 +1218,66 @@ impl Display for Valid {
     }
 }
 
-const FORBIDDEN_HOST_FN_PREFIX: &[&str; 1] = &["ipfs"];
+/// A file linked from a manifest, stored either on IPFS or on Arweave.
+#[derive(Debug)]
+enum FileId {
+    Ipfs(Cid),
+    // The transaction ID.
+    Arweave(String),
+}
+
+impl Display for FileId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileId::Ipfs(cid) => write!(f, "{}", cid),
+            FileId::Arweave(tx_id) => write!(f, "ar://{}", tx_id),
+        }
+    }
+}
 
 enum Invalid {
     BadCid(String),
-    Unavailable(Cid, Error),
+    BadArweaveTxId(String),
+    Unavailable(FileId, Error),
+    // The size seen when giving up, and the limit.
+    FileTooLarge(FileId, u64, u64),
+    // A link to something other than a readable file, such as a directory or a non-UnixFS file.
+    UnverifiableFile(Cid, Error),
     ManifestParseError(Error),
     SchemaParseError(Error),
+    SchemaValidationError(Vec<schema::SchemaError>),
     WasmParseError(Error),
+    WasmValidationError(Error),
+    // The handler named in the manifest.
+    MissingHandlerExport(String),
+    // What is excessive, the size and the limit.
+    ExcessiveMemory(&'static str, u64, u64),
     AbiParseError(Error),
-    ForbiddenApi(String),
+    // The `source.abi` that is not in `mapping.abis`.
+    UnknownAbi(String),
+    // The ABI name and the event or function signature of the handler.
+    MissingAbiEvent(String, String),
+    MissingAbiFunction(String, String),
+    // The module and the name of the imported function.
+    ForbiddenApi(String, String),
     UnsupportedNetwork(String),
     UnsupportedDataSourceKind(String),
+    UnsupportedFeature(String),
+    UnsupportedSpecVersion(Version),
+    UnsupportedApiVersion(Version),
+    // The graft base, and why it is invalid.
+    BadGraftBase(Cid, Box<Invalid>),
+    // The base that was already in the chain of grafts.
+    GraftCycle(Cid),
+    // The maximum depth.
+    GraftTooDeep(usize),
+    SubstreamsPackageParseError(Error),
+    // The `moduleName` of the data source.
+    SubstreamsModuleNotFound(String),
+    // The module and what it outputs.
+    SubstreamsModuleOutputType(String, String),
+    // The timeout that was exceeded.
+    CheckTimeout(Duration),
 }
 
 impl Display for Invalid {
@@ -545,14 +1286,119 @@ impl Display for Invalid {
 
         match self {
             BadCid(cid) => write!(f, "bad cid: {}", cid),
-            Unavailable(cid, e) => write!(f, "unavailable cid: {} ({})", cid, e),
+            BadArweaveTxId(tx_id) => write!(f, "bad arweave transaction id: {}", tx_id),
+            Unavailable(file, e) => write!(f, "unavailable file: {} ({})", file, e),
+            FileTooLarge(file, size, limit) => write!(
+                f,
+                "file too large: {} ({} bytes, limit {})",
+                file, size, limit
+            ),
+            UnverifiableFile(cid, e) => write!(f, "unverifiable file: {} ({})", cid, e),
             ManifestParseError(e) => write!(f, "manifest parse error: {}", e),
             SchemaParseError(e) => write!(f, "schema parse error: {}", e),
+            SchemaValidationError(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "schema validation error: {}", errors.join("; "))
+            }
             WasmParseError(e) => write!(f, "wasm parse error: {}", e),
+            WasmValidationError(e) => write!(f, "wasm validation error: {}", e),
+            MissingHandlerExport(handler) => {
+                write!(f, "handler not exported by the mapping: {}", handler)
+            }
+            ExcessiveMemory(what, size, limit) => {
+                write!(f, "excessive memory: {} {}, limit {}", size, what, limit)
+            }
             AbiParseError(e) => write!(f, "abi parse error: {}", e),
-            ForbiddenApi(api) => write!(f, "use of forbidden api: {}", api),
+            UnknownAbi(abi) => write!(f, "source abi not in the mapping abis: {}", abi),
+            MissingAbiEvent(abi, event) => {
+                write!(
+                    f,
+                    "event handler for an event not in abi {}: {}",
+                    abi, event
+                )
+            }
+            MissingAbiFunction(abi, function) => write!(
+                f,
+                "call handler for a function not in abi {}: {}",
+                abi, function
+            ),
+            ForbiddenApi(module, function) => write!(
+                f,
+                "use of forbidden api: {} imported from module {}",
+                function, module
+            ),
             UnsupportedNetwork(network_id) => write!(f, "unsupported network: {}", network_id),
             UnsupportedDataSourceKind(kind) => write!(f, "unsupported data source kind: {}", kind),
+            UnsupportedFeature(feature) => write!(f, "unsupported feature: {}", feature),
+            UnsupportedSpecVersion(version) => write!(f, "unsupported spec version: {}", version),
+            UnsupportedApiVersion(version) => write!(f, "unsupported api version: {}", version),
+            BadGraftBase(base, e) => write!(f, "invalid graft base {}: {}", base, e),
+            GraftCycle(base) => write!(f, "graft cycle through {}", base),
+            GraftTooDeep(depth) => write!(f, "more than {} grafts deep", depth),
+            SubstreamsPackageParseError(e) => write!(f, "substreams package parse error: {}", e),
+            SubstreamsModuleNotFound(module) => {
+                write!(f, "substreams module not in the package: {}", module)
+            }
+            SubstreamsModuleOutputType(module, output) => write!(
+                f,
+                "substreams module {} has an unusable output: {}",
+                module, output
+            ),
+            CheckTimeout(timeout) => write!(
+                f,
+                "check did not complete within {} seconds",
+                timeout.as_secs()
+            ),
+        }
+    }
+}
+
+impl Invalid {
+    /// The name of the variant, for reports.
+    fn kind(&self) -> &'static str {
+        use Invalid::*;
+
+        match self {
+            BadCid(_) => "BadCid",
+            BadArweaveTxId(_) => "BadArweaveTxId",
+            Unavailable(_, _) => "Unavailable",
+            FileTooLarge(..) => "FileTooLarge",
+            UnverifiableFile(..) => "UnverifiableFile",
+            ManifestParseError(_) => "ManifestParseError",
+            SchemaParseError(_) => "SchemaParseError",
+            SchemaValidationError(_) => "SchemaValidationError",
+            WasmParseError(_) => "WasmParseError",
+            WasmValidationError(_) => "WasmValidationError",
+            MissingHandlerExport(_) => "MissingHandlerExport",
+            ExcessiveMemory(..) => "ExcessiveMemory",
+            AbiParseError(_) => "AbiParseError",
+            UnknownAbi(_) => "UnknownAbi",
+            MissingAbiEvent(..) => "MissingAbiEvent",
+            MissingAbiFunction(..) => "MissingAbiFunction",
+            ForbiddenApi(..) => "ForbiddenApi",
+            UnsupportedNetwork(_) => "UnsupportedNetwork",
+            UnsupportedDataSourceKind(_) => "UnsupportedDataSourceKind",
+            UnsupportedFeature(_) => "UnsupportedFeature",
+            UnsupportedSpecVersion(_) => "UnsupportedSpecVersion",
+            UnsupportedApiVersion(_) => "UnsupportedApiVersion",
+            BadGraftBase(..) => "BadGraftBase",
+            GraftCycle(_) => "GraftCycle",
+            GraftTooDeep(_) => "GraftTooDeep",
+            SubstreamsPackageParseError(_) => "SubstreamsPackageParseError",
+            SubstreamsModuleNotFound(_) => "SubstreamsModuleNotFound",
+            SubstreamsModuleOutputType(..) => "SubstreamsModuleOutputType",
+            CheckTimeout(_) => "CheckTimeout",
+        }
+    }
+
+    /// Whether the deployment is invalid because a file couldn't be fetched, including a file of
+    /// its graft base, rather than because of its contents. These are what an IPFS outage looks
+    /// like to the circuit breaker. A check that timed out is not, it failed as a whole.
+    fn is_unavailable(&self) -> bool {
+        match self {
+            Invalid::Unavailable(..) => true,
+            Invalid::BadGraftBase(_, e) => e.is_unavailable(),
+            _ => false,
         }
     }
 }
@@ -566,80 +1412,175 @@ impl From<IpfsError> for CheckError {
     fn from(e: IpfsError) -> CheckError {
         match e {
             IpfsError::GatewayTimeout(cid, err) => {
-                CheckError::Invalid(Invalid::Unavailable(cid, err))
+                CheckError::Invalid(Invalid::Unavailable(FileId::Ipfs(cid), err))
             }
             IpfsError::ClientTimeout(cid, err) => {
-                CheckError::Invalid(Invalid::Unavailable(cid, err))
+                CheckError::Invalid(Invalid::Unavailable(FileId::Ipfs(cid), err))
+            }
+            IpfsError::NotFound(cid, err) => {
+                CheckError::Invalid(Invalid::Unavailable(FileId::Ipfs(cid), err))
+            }
+            IpfsError::TooLarge(cid, size, limit) => {
+                CheckError::Invalid(Invalid::FileTooLarge(FileId::Ipfs(cid), size, limit))
+            }
+            // Only reached when no endpoint served the content of the file.
+            IpfsError::ContentMismatch(cid, err) => {
+                CheckError::Invalid(Invalid::Unavailable(FileId::Ipfs(cid), err))
+            }
+            IpfsError::Unsupported(cid, err) => {
+                CheckError::Invalid(Invalid::UnverifiableFile(cid, err))
             }
-            IpfsError::NotFound(cid, err) => CheckError::Invalid(Invalid::Unavailable(cid, err)),
             IpfsError::Other(e) => CheckError::Other(e),
         }
     }
 }
 
+impl From<ArweaveError> for CheckError {
+    fn from(e: ArweaveError) -> CheckError {
+        match e {
+            ArweaveError::Timeout(tx_id, err) | ArweaveError::NotFound(tx_id, err) => {
+                CheckError::Invalid(Invalid::Unavailable(FileId::Arweave(tx_id), err))
+            }
+            ArweaveError::TooLarge(tx_id, size, limit) => {
+                CheckError::Invalid(Invalid::FileTooLarge(FileId::Arweave(tx_id), size, limit))
+            }
+            ArweaveError::Other(e) => CheckError::Other(e),
+        }
+    }
+}
+
 impl From<Invalid> for CheckError {
     fn from(e: Invalid) -> CheckError {
         CheckError::Invalid(e)
     }
 }
 
+/// Grafts followed from a deployment before giving up on reaching the first base.
+const MAX_GRAFT_DEPTH: usize = 16;
+
 /// Check availability and validity for the manifest and all files linked from it.
 /// This requires downloading and parsing the manifest and liked files.
 /// An error is a generic networking error from the IPFS request.
+#[allow(clippy::too_many_arguments)]
 async fn check(
     ipfs: &impl Ipfs,
+    arweave: &impl Arweave,
     deployment_id: Cid,
     supported_networks: &[String],
     supported_ds_kinds: &[String],
+    supported_features: &[String],
+    forbidden_host_fns: &[String],
+    file_size_limits: &FileSizeLimits,
+    version_bounds: &VersionBounds,
 ) -> Result<(), CheckError> {
-    fn check_link(file: &manifest::Link) -> Result<Cid, Invalid> {
-        Cid::from_str(file.link.trim_start_matches("/ipfs/"))
-            .map_err(|_| Invalid::BadCid(file.link.to_string()))
-    }
-
-    fn calls_any_host_fn<'a>(
-        mapping: &'a [u8],
-        host_fn_prefixes: &[&str],
-    ) -> Result<Option<&'a str>, Error> {
-        use wasmparser::Payload;
-
-        for payload in wasmparser::Parser::new(0).parse_all(mapping) {
-            if let Payload::ImportSection(s) = payload? {
-                for import in s {
-                    let name = import?.name;
-                    if host_fn_prefixes.iter().any(|p| name.starts_with(p)) {
-                        return Ok(Some(name));
-                    }
-                }
+    // Check the deployment, then its graft base, the base of that, and so on.
+    let mut grafts = Vec::new();
+    let mut deployment = deployment_id;
+    loop {
+        let graft = match check_deployment(
+            ipfs,
+            arweave,
+            deployment,
+            supported_networks,
+            supported_ds_kinds,
+            supported_features,
+            forbidden_host_fns,
+            file_size_limits,
+            version_bounds,
+        )
+        .await
+        {
+            Ok(graft) => graft,
+            Err(CheckError::Invalid(e)) if deployment != deployment_id => {
+                return Err(Invalid::BadGraftBase(deployment, Box::new(e)).into())
+            }
+            Err(e) => return Err(e),
+        };
+
+        let base = match graft {
+            Some(graft) => {
+                Cid::from_str(&graft.base).map_err(|_| Invalid::BadCid(graft.base.clone()))?
             }
+            None => return Ok(()),
+        };
+        if base == deployment_id || grafts.contains(&base) {
+            return Err(Invalid::GraftCycle(base).into());
+        }
+        grafts.push(base);
+        if grafts.len() > MAX_GRAFT_DEPTH {
+            return Err(Invalid::GraftTooDeep(MAX_GRAFT_DEPTH).into());
         }
+        deployment = base;
+    }
+}
+
+/// Checks a single deployment, returning its graft if it has one.
+#[allow(clippy::too_many_arguments)]
+async fn check_deployment(
+    ipfs: &impl Ipfs,
+    arweave: &impl Arweave,
+    deployment_id: Cid,
+    supported_networks: &[String],
+    supported_ds_kinds: &[String],
+    supported_features: &[String],
+    forbidden_host_fns: &[String],
+    file_size_limits: &FileSizeLimits,
+    version_bounds: &VersionBounds,
+) -> Result<Option<manifest::Graft>, CheckError> {
+    fn check_link(file: &manifest::Link) -> Result<Cid, Invalid> {
+        file.cid()
+            .ok_or_else(|| Invalid::BadCid(file.link.to_string()))
+    }
 
-        Ok(None)
+    fn check_arweave_link(file: &manifest::Link) -> Result<&str, Invalid> {
+        arweave::tx_id(&file.link).ok_or_else(|| Invalid::BadArweaveTxId(file.link.to_string()))
     }
 
     // Check if the manifest is available and valid yaml.
     let manifest: Manifest = {
-        let raw_manifest = ipfs.cat(deployment_id).await?;
+        let raw_manifest = ipfs.cat(deployment_id, file_size_limits.manifest).await?;
         match serde_yaml::from_slice(&raw_manifest) {
             Ok(manifest) => manifest,
             Err(e) => return Err(Invalid::ManifestParseError(e.into()).into()),
         }
     };
 
+    // Check the spec version.
+    let spec_version = Version::parse(&manifest.spec_version).map_err(|e| {
+        Invalid::ManifestParseError(anyhow!("bad specVersion {}: {}", manifest.spec_version, e))
+    })?;
+    version_bounds.spec_version(&spec_version)?;
+
+    // Check the features.
+    for feature in &manifest.features {
+        if !supported_features.contains(feature) {
+            return Err(Invalid::UnsupportedFeature(feature.clone()).into());
+        }
+    }
+
     // Check the schema.
     {
         let schema_cid = check_link(&manifest.schema.file)?;
-        let raw_schema = String::from_utf8(ipfs.cat(schema_cid).await?.to_vec())
-            .map_err(|e| Invalid::SchemaParseError(e.into()))?;
-        graphql_parser::parse_schema::<&str>(&raw_schema)
+        let raw_schema = String::from_utf8(
+            ipfs.cat(schema_cid, file_size_limits.schema)
+                .await?
+                .to_vec(),
+        )
+        .map_err(|e| Invalid::SchemaParseError(e.into()))?;
+        let schema = graphql_parser::parse_schema::<&str>(&raw_schema)
             .map_err(|e| Invalid::SchemaParseError(e.into()))?;
+        let errors = schema::validate(&schema);
+        if !errors.is_empty() {
+            return Err(Invalid::SchemaValidationError(errors).into());
+        }
     }
 
     let mut network = None;
     for DataSource {
         kind,
-        mapping: Mapping { file, abis },
+        mapping,
         network: ds_network,
+        source,
     } in manifest.data_sources()
     {
         // Check data source kind
@@ -667,24 +1608,81 @@ async fn check(
         }
 
         // Check that ABIs are valid.
-        for Abi { file } in abis {
-            ethabi::Contract::load(ipfs.cat(check_link(file)?).await?.as_ref())
+        let mut contracts = HashMap::new();
+        for Abi { name, file } in &mapping.abis {
+            let abi = ipfs.cat(check_link(file)?, file_size_limits.abi).await?;
+            let contract = ethabi::Contract::load(abi.as_ref())
                 .map_err(|e| Invalid::AbiParseError(e.into()))?;
+            contracts.insert(name, contract);
         }
 
-        // Check mappings.
-        if let Some(file) = file {
-            let wasm = ipfs.cat(check_link(file)?).await?;
-            if let Some(host_fn) = calls_any_host_fn(&wasm, FORBIDDEN_HOST_FN_PREFIX)
-                .map_err(Invalid::WasmParseError)?
-            {
-                return Err(Invalid::ForbiddenApi(host_fn.to_string()).into());
+        // Check that the ABI of the source is listed, and has what the handlers are for.
+        if let Some(abi) = source.as_ref().and_then(|source| source.abi.as_ref()) {
+            let contract = contracts
+                .get(abi)
+                .ok_or_else(|| Invalid::UnknownAbi(abi.clone()))?;
+            abi::check_handlers(mapping, abi, contract)?;
+        }
+
+        // Check the package of substreams data sources.
+        if kind == "substreams" {
+            let package = source
+                .as_ref()
+                .and_then(|source| source.package.as_ref())
+                .ok_or_else(|| {
+                    Invalid::ManifestParseError(anyhow!("substreams data source without a package"))
+                })?;
+            // Packages bundle the WASM of their modules, so they are held to the WASM size limit.
+            let raw_package = ipfs
+                .cat(check_link(&package.file)?, file_size_limits.wasm)
+                .await?;
+            substreams::check_package(
+                &raw_package,
+                &package.module_name,
+                mapping.handler.is_some(),
+            )?;
+        }
+
+        // Check the file of file data sources, which is stored where the kind says.
+        if let Some(file) = source.as_ref().and_then(|source| source.file.as_ref()) {
+            match kind.as_str() {
+                "file/ipfs" => {
+                    ipfs.cat(check_link(file)?, file_size_limits.file_source)
+                        .await?;
+                }
+                "file/arweave" => {
+                    arweave
+                        .get(check_arweave_link(file)?, file_size_limits.file_source)
+                        .await?;
+                }
+                _ => (),
             }
         }
+
+        // Check the API version, which a mapping with a WASM file must have.
+        let api_version = match mapping.api_version.as_deref() {
+            Some(api_version) => {
+                let api_version = Version::parse(api_version).map_err(|e| {
+                    Invalid::ManifestParseError(anyhow!("bad apiVersion {}: {}", api_version, e))
+                })?;
+                version_bounds.api_version(&api_version)?;
+                Some(api_version)
+            }
+            None => None,
+        };
+
+        // Check mappings.
+        if let Some(file) = &mapping.file {
+            let api_version = api_version.ok_or_else(|| {
+                Invalid::ManifestParseError(anyhow!("mapping without apiVersion"))
+            })?;
+            let wasm = ipfs.cat(check_link(file)?, file_size_limits.wasm).await?;
+            wasm::check_mapping(&wasm, &api_version, forbidden_host_fns, mapping.handlers())?;
+        }
     }
 
     // All validations have passed.
-    Ok(())
+    Ok(manifest.graft)
 }
 
 struct Metrics {
@@ -692,6 +1690,13 @@ struct Metrics {
     reconcile_runs_ok: prometheus::IntCounter,
     reconcile_runs_err: prometheus::IntCounter,
     valid_deployment_cache_hits: prometheus::IntCounter,
+    circuit_breaker_trips_total: prometheus::IntCounter,
+    circuit_breaker_aborted_changes: prometheus::IntGauge,
+    network_subgraph_block_number: prometheus::IntGauge,
+    network_subgraph_lag: prometheus::IntGauge,
+    left_over_deployments: prometheus::IntGauge,
+    check_timeouts_total: prometheus::IntCounter,
+    malformed_deployments_total: prometheus::IntCounter,
 }
 
 lazy_static! {
@@ -721,6 +1726,41 @@ impl Metrics {
                 "Total valid deployment cache hits"
             )
             .unwrap(),
+            circuit_breaker_trips_total: prometheus::register_int_counter!(
+                "circuit_breaker_trips_total",
+                "Total reconcile runs whose new denials were held back because of the circuit breaker"
+            )
+            .unwrap(),
+            circuit_breaker_aborted_changes: prometheus::register_int_gauge!(
+                "circuit_breaker_aborted_changes",
+                "New denials held back by the last run because of the circuit breaker"
+            )
+            .unwrap(),
+            network_subgraph_block_number: prometheus::register_int_gauge!(
+                "network_subgraph_block_number",
+                "The network subgraph block the last run was based on"
+            )
+            .unwrap(),
+            network_subgraph_lag: prometheus::register_int_gauge!(
+                "network_subgraph_lag",
+                "How many blocks the network subgraph was behind the chain head in the last run"
+            )
+            .unwrap(),
+            left_over_deployments: prometheus::register_int_gauge!(
+                "left_over_deployments",
+                "Deployments the last run did not get to check, left over for the next run"
+            )
+            .unwrap(),
+            check_timeouts_total: prometheus::register_int_counter!(
+                "check_timeouts_total",
+                "Total deployment checks abandoned for exceeding --check-timeout-secs"
+            )
+            .unwrap(),
+            malformed_deployments_total: prometheus::register_int_counter!(
+                "malformed_deployments_total",
+                "Total network subgraph deployments skipped for being malformed"
+            )
+            .unwrap(),
         }
     }
 }
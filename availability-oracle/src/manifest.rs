@@ -1,4 +1,6 @@
 use serde::Deserialize;
+use std::str::FromStr;
+use tiny_cid::Cid;
 
 #[derive(Clone, Debug, Deserialize)]
 pub(crate) struct Link {
@@ -6,6 +8,19 @@ pub(crate) struct Link {
     pub(crate) link: String,
 }
 
+impl Link {
+    /// The CID the link points to, either v0 or v1. The CID may be prefixed with `/ipfs/` or
+    /// `ipfs://`.
+    pub(crate) fn cid(&self) -> Option<Cid> {
+        let cid = self
+            .link
+            .strip_prefix("ipfs://")
+            .or_else(|| self.link.strip_prefix("/ipfs/"))
+            .unwrap_or(&self.link);
+        Cid::from_str(cid).ok()
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub(crate) struct File {
     pub(crate) file: Link,
@@ -13,29 +28,98 @@ pub(crate) struct File {
 
 #[derive(Clone, Debug, Deserialize)]
 pub(crate) struct Abi {
+    pub(crate) name: String,
     pub(crate) file: Link,
 }
 
 #[derive(Clone, Debug, Deserialize)]
+pub(crate) struct EventHandler {
+    pub(crate) event: String,
+    pub(crate) handler: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct CallHandler {
+    pub(crate) function: String,
+    pub(crate) handler: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct BlockHandler {
+    pub(crate) handler: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct Mapping {
     pub(crate) file: Option<Link>,
     #[serde(default)]
     pub(crate) abis: Vec<Abi>,
+    pub(crate) api_version: Option<String>,
+    #[serde(default)]
+    pub(crate) event_handlers: Vec<EventHandler>,
+    #[serde(default)]
+    pub(crate) call_handlers: Vec<CallHandler>,
+    #[serde(default)]
+    pub(crate) block_handlers: Vec<BlockHandler>,
+    // The handler of a file data source.
+    pub(crate) handler: Option<String>,
+}
+
+impl Mapping {
+    /// The names of all the handlers the mapping must export.
+    pub(crate) fn handlers(&self) -> impl Iterator<Item = &str> {
+        self.event_handlers
+            .iter()
+            .map(|h| h.handler.as_str())
+            .chain(self.call_handlers.iter().map(|h| h.handler.as_str()))
+            .chain(self.block_handlers.iter().map(|h| h.handler.as_str()))
+            .chain(self.handler.as_deref())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Package {
+    pub(crate) module_name: String,
+    pub(crate) file: Link,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Source {
+    // The name of the ABI of the contract, one of the `mapping.abis`.
+    pub(crate) abi: Option<String>,
+    // The package of a substreams data source.
+    pub(crate) package: Option<Package>,
+    // The file of a file data source, a CID for `file/ipfs` and a transaction ID for
+    // `file/arweave`.
+    pub(crate) file: Option<Link>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub(crate) struct DataSource {
     pub(crate) kind: String,
     pub(crate) network: Option<String>,
+    pub(crate) source: Option<Source>,
     pub(crate) mapping: Mapping,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Graft {
+    // The deployment ID of the base.
+    pub(crate) base: String,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct Manifest {
+    pub(crate) spec_version: String,
     pub(crate) schema: File,
     data_sources: Vec<DataSource>,
     templates: Option<Vec<DataSource>>,
+    pub(crate) graft: Option<Graft>,
+    #[serde(default)]
+    pub(crate) features: Vec<String>,
 }
 
 impl Manifest {
@@ -45,3 +129,24 @@ impl Manifest {
             .chain(self.templates.iter().flatten())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_cid() {
+        let link = |link: &str| Link {
+            link: link.to_string(),
+        };
+        let v0 = "QmWt3abizzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+        let v1 = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+        for v in [v0, v1] {
+            let cid = Cid::from_str(v).unwrap();
+            assert_eq!(link(v).cid(), Some(cid));
+            assert_eq!(link(&format!("/ipfs/{}", v)).cid(), Some(cid));
+            assert_eq!(link(&format!("ipfs://{}", v)).cid(), Some(cid));
+        }
+        assert_eq!(link("./Contract.wasm").cid(), None);
+    }
+}
@@ -1,8 +1,8 @@
 use crate::util;
+use async_trait::async_trait;
 use common::prelude::*;
 use futures::stream;
 use futures::Stream;
-use multibase::Base;
 use reqwest::Client;
 use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -10,48 +10,127 @@ use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
+use tiny_cid::Cid;
 
 #[derive(Copy, Clone)]
 pub struct SubgraphDeployment {
     pub id: [u8; 32],
 
-    // In GRT wei (1/10^18 of a GRT).
+    /// The CID of the manifest, the `ipfsHash` of the deployment.
+    pub manifest: Cid,
+
+    // The curation signal, in GRT wei (1/10^18 of a GRT).
     pub signal_amount: u128,
+
+    // The tokens allocated to the deployment, in GRT wei.
+    pub staked_tokens: u128,
+
+    // Seconds since the unix epoch.
+    pub created_at: u64,
+
+    // When the deployment was denied, 0 if it isn't.
+    pub denied_at: u64,
     pub deny: bool,
 }
 
 impl SubgraphDeployment {
     pub fn ipfs_hash(&self) -> String {
-        let cid = util::bytes32_to_cid_v0(self.id);
-        // Unwrap: This is a valid CIDv0 being encoded to Base58
-        cid.to_string_of_base(Base::Base58Btc).unwrap()
+        self.manifest.to_string()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawSubgraphDeployment {
+    id: String,
+    ipfs_hash: String,
+    signalled_tokens: String,
+    staked_tokens: String,
+    created_at: u64,
+    denied_at: u64,
+}
+
+impl TryFrom<RawSubgraphDeployment> for SubgraphDeployment {
+    type Error = Error;
+
+    fn try_from(raw: RawSubgraphDeployment) -> Result<Self, Error> {
+        let id = hex::decode(raw.id.trim_start_matches("0x"))
+            .ok()
+            .and_then(|id| <[u8; 32]>::try_from(id).ok())
+            .ok_or_else(|| anyhow!("bad deployment id {}", raw.id))?;
+        let manifest = Cid::from_str(&raw.ipfs_hash).map_err(|e| {
+            anyhow!(
+                "bad ipfsHash {} of deployment {}: {}",
+                raw.ipfs_hash,
+                raw.id,
+                e
+            )
+        })?;
+        let manifest_id = util::cid_to_bytes32(&manifest)
+            .map_err(|e| anyhow!("bad ipfsHash of deployment {}: {}", raw.id, e))?;
+        if manifest_id != id {
+            return Err(anyhow!(
+                "ipfsHash {} does not match deployment id {}",
+                raw.ipfs_hash,
+                raw.id
+            ));
+        }
+        let tokens = |field: &str, value: &str| {
+            u128::from_str(value)
+                .map_err(|e| anyhow!("bad {} {} of deployment {}: {}", field, value, raw.id, e))
+        };
+
+        Ok(SubgraphDeployment {
+            id,
+            manifest,
+            signal_amount: tokens("signalledTokens", &raw.signalled_tokens)?,
+            staked_tokens: tokens("stakedTokens", &raw.staked_tokens)?,
+            created_at: raw.created_at,
+            denied_at: raw.denied_at,
+            deny: raw.denied_at > 0,
+        })
     }
 }
 
+/// The indexing status of the network subgraph, from its `_meta` field.
+#[derive(Clone, Debug)]
+pub struct NetworkSubgraphMeta {
+    pub block_number: u64,
+    pub block_hash: Option<String>,
+    pub deployment: String,
+    pub has_indexing_errors: bool,
+}
+
 /// Necessary interactions from the network subgraph.
+#[async_trait]
 pub trait NetworkSubgraph {
+    async fn meta(&self) -> Result<NetworkSubgraphMeta, Error>;
+
+    /// The deployments as of `block`.
     fn deployments_over_threshold(
         self: Arc<Self>,
         curation_threshold: u64,
         grace_period: Duration,
+        block: u64,
     ) -> Pin<Box<dyn Stream<Item = Result<SubgraphDeployment, Error>>>>;
 }
 
 pub struct NetworkSubgraphImpl {
     logger: Logger,
-    endpoint: String,
-    client: Client,
+    transport: Box<dyn Transport>,
 }
 
 impl NetworkSubgraphImpl {
     pub fn new(logger: Logger, endpoint: String) -> Arc<Self> {
         Arc::new(NetworkSubgraphImpl {
             logger,
-            endpoint,
-            client: Client::builder()
-                .timeout(Duration::from_secs(60))
-                .build()
-                .unwrap(),
+            transport: Box::new(HttpTransport {
+                endpoint,
+                client: Client::builder()
+                    .timeout(Duration::from_secs(60))
+                    .build()
+                    .unwrap(),
+            }),
         })
     }
 }
@@ -68,22 +147,192 @@ struct GraphqlResponse {
     errors: Option<Vec<serde_json::Value>>,
 }
 
+/// Sends GraphQL requests to the network subgraph.
+#[async_trait]
+trait Transport: Send + Sync {
+    async fn send(&self, req: &GraphqlRequest) -> Result<GraphqlResponse, Error>;
+}
+
+struct HttpTransport {
+    endpoint: String,
+    client: Client,
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn send(&self, req: &GraphqlRequest) -> Result<GraphqlResponse, Error> {
+        Ok(self
+            .client
+            .post(&self.endpoint)
+            .json(req)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+const META_QUERY: &str = r#"
+query {
+    _meta {
+        block {
+            number
+            hash
+        }
+        deployment
+        hasIndexingErrors
+    }
+}
+"#;
+
 const DEPLOYMENTS_QUERY: &str = r#"
-query($threshold: BigInt!, $max_creation: Int!, $skip: Int!) {
-    subgraphDeployments(first: 1000, skip: $skip, where: { signalledTokens_gt: $threshold, createdAt_lt: $max_creation }) {
+query($threshold: BigInt!, $max_creation: Int!, $block: Int!, $last_id: ID!) {
+    subgraphDeployments(
+        first: 1000,
+        block: { number: $block },
+        orderBy: id,
+        orderDirection: asc,
+        where: { id_gt: $last_id, signalledTokens_gt: $threshold, createdAt_lt: $max_creation }
+    ) {
         id
+        ipfsHash
+        signalledTokens
         stakedTokens
+        createdAt
         deniedAt
     }
 }
 "#;
 
+impl NetworkSubgraphImpl {
+    /// Runs a query, returning the `data` of the response.
+    async fn query(
+        &self,
+        query: &str,
+        variables: BTreeMap<String, serde_json::Value>,
+    ) -> Result<BTreeMap<String, serde_json::Value>, Error> {
+        let req = GraphqlRequest {
+            query: query.to_string(),
+            variables,
+        };
+
+        let res = self.transport.send(&req).await?;
+
+        if let Some(errs) = res.errors.filter(|errs| !errs.is_empty()) {
+            return Err(anyhow!(
+                "error querying the network subgraph {}",
+                serde_json::to_string(&errs)?
+            ));
+        }
+        res.data
+            .ok_or_else(|| anyhow!("the network subgraph returned no data"))
+    }
+
+    /// A page of the deployments at `block` with an id after `last_id`, along with the id of the
+    /// last one.
+    async fn deployments_page(
+        &self,
+        curation_threshold: u128,
+        max_creation: i32,
+        block: u64,
+        last_id: &str,
+    ) -> Result<(Vec<SubgraphDeployment>, Option<String>), Error> {
+        let variables = vec![
+            (
+                "threshold".to_string(),
+                curation_threshold.to_string().into(),
+            ),
+            ("max_creation".to_string(), max_creation.into()),
+            ("block".to_string(), block.into()),
+            ("last_id".to_string(), last_id.into()),
+        ]
+        .into_iter()
+        .collect();
+
+        let data = self
+            .query(DEPLOYMENTS_QUERY, variables)
+            .await?
+            .remove("subgraphDeployments")
+            .ok_or_else(|| anyhow!("the network subgraph returned no `subgraphDeployments`"))?;
+
+        // A malformed deployment is skipped rather than failing the page, which would fail every
+        // run for as long as the deployment exists. Only its id is needed to get the next page.
+        let page: Vec<serde_json::Value> = serde_json::from_value(data)?;
+        let last_id = page
+            .last()
+            .map(|row| {
+                row["id"]
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow!("deployment without an id: {}", row))
+            })
+            .transpose()?;
+        let page = page
+            .into_iter()
+            .filter_map(|row| {
+                match serde_json::from_value::<RawSubgraphDeployment>(row)
+                    .map_err(Error::from)
+                    .and_then(SubgraphDeployment::try_from)
+                {
+                    Ok(deployment) => Some(deployment),
+                    Err(e) => {
+                        crate::METRICS.malformed_deployments_total.inc();
+                        warn!(self.logger, "Skipping malformed deployment";
+                            "error" => format!("{:#}", e)
+                        );
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        trace!(self.logger, "deployments page"; "page_size" => page.len(), "block" => block);
+
+        Ok((page, last_id))
+    }
+}
+
+#[async_trait]
 impl NetworkSubgraph for NetworkSubgraphImpl {
+    async fn meta(&self) -> Result<NetworkSubgraphMeta, Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RawMeta {
+            block: RawBlock,
+            deployment: String,
+            has_indexing_errors: bool,
+        }
+
+        #[derive(Deserialize)]
+        struct RawBlock {
+            number: u64,
+            hash: Option<String>,
+        }
+
+        let meta = self
+            .query(META_QUERY, BTreeMap::new())
+            .await?
+            .remove("_meta")
+            .ok_or_else(|| anyhow!("the network subgraph returned no `_meta`"))?;
+        let meta: RawMeta = serde_json::from_value(meta)?;
+        Ok(NetworkSubgraphMeta {
+            block_number: meta.block.number,
+            block_hash: meta.block.hash,
+            deployment: meta.deployment,
+            has_indexing_errors: meta.has_indexing_errors,
+        })
+    }
+
     // The `curation_threshold` is denominated in GRT.
+    //
+    // Pages are queried by id rather than with `skip`, which graph-node handles poorly for large
+    // values, and all at the same block, so that a run sees a consistent snapshot of the network.
     fn deployments_over_threshold(
         self: Arc<Self>,
         curation_threshold: u64,
         grace_period: Duration,
+        block: u64,
     ) -> Pin<Box<dyn Stream<Item = Result<SubgraphDeployment, Error>>>> {
         // Convert the threshold to wei.
         let wei_factor: u128 = 10_u128.pow(18);
@@ -94,81 +343,160 @@ impl NetworkSubgraph for NetworkSubgraphImpl {
             .unwrap();
         let max_creation = (unix_epoch - grace_period).as_secs() as i32;
 
-        stream::iter((0..).step_by(1000))
-            .then(move |skip| {
-                let this = self.clone();
-                async move {
-                    let req = GraphqlRequest {
-                        query: DEPLOYMENTS_QUERY.to_string(),
-                        variables: vec![
-                            (
-                                "threshold".to_string(),
-                                curation_threshold.to_string().into(),
-                            ),
-                            ("max_creation".to_string(), max_creation.into()),
-                            ("skip".to_string(), skip.into()),
-                        ]
-                        .into_iter()
-                        .collect(),
-                    };
-
-                    let res: GraphqlResponse = this
-                        .client
-                        .post(&this.endpoint)
-                        .json(&req)
-                        .send()
-                        .await?
-                        .error_for_status()?
-                        .json()
-                        .await?;
-
-                    if let Some(errs) = res.errors.filter(|errs| !errs.is_empty()) {
-                        return Err(anyhow!(
-                            "error querying deployments from subgraph {}",
-                            serde_json::to_string(&errs)?
-                        ));
-                    }
+        // The state is the id of the last deployment seen, or `None` after the last page.
+        stream::try_unfold(
+            (self, Some(String::new())),
+            move |(this, last_id)| async move {
+                let last_id = match last_id {
+                    Some(last_id) => last_id,
+                    None => return Ok::<_, Error>(None),
+                };
+                let (page, last_id) = this
+                    .deployments_page(curation_threshold, max_creation, block, &last_id)
+                    .await?;
+                Ok(Some((page, (this, last_id))))
+            },
+        )
+        .map_ok(|deployments| stream::iter(deployments.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+    }
+}
 
-                    // Unwrap: A response that has no errors must contain data, the response must
-                    // contain a `subgraphDeployments` key.
-                    let data = res.data.unwrap().remove("subgraphDeployments").unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
 
-                    #[derive(Deserialize)]
-                    #[allow(non_snake_case)]
-                    struct RawSubgraphDeployment {
-                        id: String,
-                        stakedTokens: String,
-                        deniedAt: u32,
-                    }
+    fn raw_deployment() -> RawSubgraphDeployment {
+        serde_json::from_value(serde_json::json!({
+            "id": "0x0101010101010101010101010101010101010101010101010101010101010101",
+            "ipfsHash": "QmNQa1FSTXNHmrjjfgUW3Px3Vkke4oKiFWdigWkYSux2Pi",
+            "signalledTokens": "1000000000000000000000",
+            "stakedTokens": "5",
+            "createdAt": 1700000000,
+            "deniedAt": 0,
+        }))
+        .unwrap()
+    }
 
-                    let page: Vec<RawSubgraphDeployment> = serde_json::from_value(data)?;
-                    let page: Vec<SubgraphDeployment> = page
-                        .into_iter()
-                        .map(|raw_deployment| SubgraphDeployment {
-                            // Unwrap: The id returned by the subgraph is a 32 byte long hexadecimal.
-                            id: <[u8; 32]>::try_from(
-                                hex::decode(raw_deployment.id.trim_start_matches("0x")).unwrap(),
-                            )
-                            .unwrap(),
-                            signal_amount: u128::from_str(&raw_deployment.stakedTokens).unwrap(),
-                            deny: raw_deployment.deniedAt > 0,
-                        })
-                        .collect();
-
-                    trace!(this.logger, "deployments page"; "page_size" => page.len());
-
-                    Ok(page)
-                }
-            })
-            .take_while(|deployments| {
-                let keep_paginating = match deployments {
-                    Ok(deployments) => !deployments.is_empty(),
-                    Err(_) => true,
-                };
-                async move { keep_paginating }
+    #[test]
+    fn test_parse_deployment() {
+        let deployment = SubgraphDeployment::try_from(raw_deployment()).unwrap();
+        assert_eq!(deployment.id, [1; 32]);
+        assert_eq!(deployment.signal_amount, 1000 * 10_u128.pow(18));
+        assert_eq!(deployment.staked_tokens, 5);
+        assert_eq!(deployment.created_at, 1700000000);
+        assert!(!deployment.deny);
+
+        let mut raw = raw_deployment();
+        raw.signalled_tokens = "1e21".to_string();
+        assert!(SubgraphDeployment::try_from(raw).is_err());
+
+        let mut raw = raw_deployment();
+        raw.id = "0x01".to_string();
+        assert!(SubgraphDeployment::try_from(raw).is_err());
+
+        // The ipfsHash of a different deployment.
+        let mut raw = raw_deployment();
+        raw.ipfs_hash = "QmWt111111111111111111111111111111111111111111".to_string();
+        assert!(SubgraphDeployment::try_from(raw).is_err());
+
+        // The ipfsHash as a CIDv1, of the same manifest.
+        let v0 = Cid::from_str(&raw_deployment().ipfs_hash).unwrap();
+        let v1 = Cid::new_v1(tiny_cid::DAG_PROTOBUF, *v0.hash());
+        let mut raw = raw_deployment();
+        raw.ipfs_hash = v1.to_string();
+        let deployment = SubgraphDeployment::try_from(raw).unwrap();
+        assert_eq!(deployment.id, [1; 32]);
+        assert_eq!(deployment.ipfs_hash(), v1.to_string());
+
+        // A raw CIDv1 can't be a deployment.
+        let mut raw = raw_deployment();
+        raw.ipfs_hash = Cid::new_v1(tiny_cid::RAW, *v0.hash()).to_string();
+        assert!(SubgraphDeployment::try_from(raw).is_err());
+    }
+
+    /// Serves `deployments`, which must be sorted by id, in pages of two, recording the variables
+    /// of each request.
+    struct MockTransport {
+        deployments: Vec<serde_json::Value>,
+        requests: Arc<Mutex<Vec<BTreeMap<String, serde_json::Value>>>>,
+    }
+
+    #[async_trait]
+    impl Transport for MockTransport {
+        async fn send(&self, req: &GraphqlRequest) -> Result<GraphqlResponse, Error> {
+            self.requests.lock().unwrap().push(req.variables.clone());
+            let last_id = req.variables["last_id"].as_str().unwrap();
+            let page: Vec<serde_json::Value> = self
+                .deployments
+                .iter()
+                .filter(|deployment| deployment["id"].as_str().unwrap() > last_id)
+                .take(2)
+                .cloned()
+                .collect();
+            Ok(GraphqlResponse {
+                data: Some(BTreeMap::from([(
+                    "subgraphDeployments".to_string(),
+                    page.into(),
+                )])),
+                errors: None,
             })
-            .map_ok(|deployments| stream::iter(deployments.into_iter().map(Ok)))
-            .try_flatten()
-            .boxed()
+        }
+    }
+
+    fn deployment_json(id: u8) -> serde_json::Value {
+        let cid = util::bytes32_to_cid_v0([id; 32]);
+        serde_json::json!({
+            "id": format!("0x{}", hex::encode([id; 32])),
+            "ipfsHash": cid.to_string(),
+            "signalledTokens": "1000000000000000000000",
+            "stakedTokens": "0",
+            "createdAt": 0,
+            "deniedAt": 0,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_paginate_deployments() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let subgraph = Arc::new(NetworkSubgraphImpl {
+            logger: common::logging::create_logger(),
+            transport: Box::new(MockTransport {
+                deployments: (1..=5)
+                    .map(|id| {
+                        let mut deployment = deployment_json(id);
+                        if id == 3 {
+                            deployment["signalledTokens"] = "1e21".into();
+                        }
+                        deployment
+                    })
+                    .collect(),
+                requests: requests.clone(),
+            }),
+        });
+
+        let deployments: Vec<SubgraphDeployment> = subgraph
+            .deployments_over_threshold(0, Duration::default(), 1234)
+            .try_collect()
+            .await
+            .unwrap();
+        let ids: Vec<u8> = deployments.iter().map(|d| d.id[0]).collect();
+        // The malformed deployment is skipped.
+        assert_eq!(ids, [1, 2, 4, 5]);
+
+        // Each page starts after the last id of the previous one, until an empty page. All of them
+        // are at the same block.
+        let requests = requests.lock().unwrap();
+        let last_ids: Vec<&str> = requests
+            .iter()
+            .map(|variables| variables["last_id"].as_str().unwrap())
+            .collect();
+        let id = |id: u8| format!("0x{}", hex::encode([id; 32]));
+        assert_eq!(last_ids, ["", &id(2), &id(4), &id(5)]);
+        assert!(requests
+            .iter()
+            .all(|variables| variables["block"] == serde_json::json!(1234)));
     }
 }
@@ -0,0 +1,59 @@
+//! A minimal reader of the protobuf wire format, for the few messages the oracle decodes.
+
+use common::prelude::*;
+
+pub(crate) fn read_varint(bytes: &mut &[u8]) -> Result<u64, Error> {
+    let mut value: u64 = 0;
+    for i in 0..10 {
+        let (&byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| anyhow!("truncated varint"))?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("varint is too long"))
+}
+
+pub(crate) enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Iterates over the `(field number, value)` pairs of a protobuf message.
+pub(crate) fn fields(mut message: &[u8]) -> impl Iterator<Item = Result<(u64, Field<'_>), Error>> {
+    std::iter::from_fn(move || {
+        if message.is_empty() {
+            return None;
+        }
+        let field = (|| {
+            let key = read_varint(&mut message)?;
+            let value = match key & 0x7 {
+                0 => Field::Varint(read_varint(&mut message)?),
+                2 => {
+                    let len = read_varint(&mut message)? as usize;
+                    ensure!(len <= message.len(), "truncated protobuf field");
+                    let (value, rest) = message.split_at(len);
+                    message = rest;
+                    Field::Bytes(value)
+                }
+                wire_type @ (1 | 5) => {
+                    let len = if wire_type == 1 { 8 } else { 4 };
+                    ensure!(len <= message.len(), "truncated protobuf field");
+                    message = &message[len..];
+                    Field::Fixed
+                }
+                wire_type => return Err(anyhow!("unsupported protobuf wire type {}", wire_type)),
+            };
+            Ok((key >> 3, value))
+        })();
+        if field.is_err() {
+            // Stop at the first error.
+            message = &[];
+        }
+        Some(field)
+    })
+}
@@ -0,0 +1,193 @@
+use common::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// The outcome of checking a single deployment in a reconcile run.
+#[derive(Clone, Debug)]
+pub struct Verdict {
+    pub id: [u8; 32],
+    pub cid: String,
+
+    // In GRT wei (1/10^18 of a GRT).
+    pub signal_amount: u128,
+
+    /// The deny flag before this run.
+    pub previous_deny: bool,
+    pub valid: bool,
+
+    /// The deny flag after this run.
+    pub deny: bool,
+
+    /// The `Invalid` variant and its message, if the deployment is invalid.
+    pub invalid_kind: Option<&'static str>,
+    pub reason: Option<String>,
+
+    /// Whether the deployment is invalid because a file couldn't be fetched, see
+    /// `Invalid::is_unavailable`.
+    pub unavailable: bool,
+
+    /// Whether the validity came from the valid deployment cache rather than a fresh check.
+    pub cached: bool,
+
+    /// Whether the change of the deny flag was not submitted because the circuit breaker tripped.
+    pub held_back: bool,
+
+    /// The network subgraph block the run was based on.
+    pub block_number: u64,
+}
+
+impl Verdict {
+    pub fn status_changed(&self) -> bool {
+        self.previous_deny != self.deny
+    }
+}
+
+/// Destination for the per-deployment verdicts of each reconcile run.
+pub trait ReportWriter {
+    fn write(&self, verdicts: &[Verdict]) -> Result<(), Error>;
+}
+
+/// Discards the verdicts, used when no report directory is configured.
+pub struct NullReportWriter;
+
+impl ReportWriter for NullReportWriter {
+    fn write(&self, _verdicts: &[Verdict]) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Writes a JSON Lines and a CSV report per run to a directory, named after the run time.
+pub struct FileReportWriter {
+    dir: PathBuf,
+}
+
+const CSV_HEADER: &str =
+    "id,cid,signal_amount,previous_deny,valid,deny,invalid_kind,reason,unavailable,cached,held_back,block_number";
+
+impl FileReportWriter {
+    pub fn new(dir: PathBuf) -> Self {
+        FileReportWriter { dir }
+    }
+}
+
+impl ReportWriter for FileReportWriter {
+    fn write(&self, verdicts: &[Verdict]) -> Result<(), Error> {
+        let run_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        fs::create_dir_all(&self.dir)?;
+        fs::write(
+            self.dir.join(format!("verdicts-{}.jsonl", run_time)),
+            to_json_lines(verdicts)?,
+        )?;
+        fs::write(
+            self.dir.join(format!("verdicts-{}.csv", run_time)),
+            to_csv(verdicts),
+        )?;
+        Ok(())
+    }
+}
+
+fn to_json_lines(verdicts: &[Verdict]) -> Result<String, Error> {
+    let mut out = String::new();
+    for verdict in verdicts {
+        let line = serde_json::json!({
+            "id": hex::encode(verdict.id),
+            "cid": &verdict.cid,
+            // As a string, since JSON numbers can't faithfully represent GRT wei.
+            "signal_amount": verdict.signal_amount.to_string(),
+            "previous_deny": verdict.previous_deny,
+            "valid": verdict.valid,
+            "deny": verdict.deny,
+            "invalid_kind": verdict.invalid_kind,
+            "reason": &verdict.reason,
+            "unavailable": verdict.unavailable,
+            "cached": verdict.cached,
+            "held_back": verdict.held_back,
+            "block_number": verdict.block_number,
+        });
+        out.push_str(&serde_json::to_string(&line)?);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn to_csv(verdicts: &[Verdict]) -> String {
+    // Quote every free text field, doubling any quotes inside it.
+    fn quote(field: &str) -> String {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+
+    let mut out = String::from(CSV_HEADER);
+    out.push('\n');
+    for verdict in verdicts {
+        let row = [
+            hex::encode(verdict.id),
+            verdict.cid.clone(),
+            verdict.signal_amount.to_string(),
+            verdict.previous_deny.to_string(),
+            verdict.valid.to_string(),
+            verdict.deny.to_string(),
+            verdict.invalid_kind.unwrap_or_default().to_string(),
+            quote(verdict.reason.as_deref().unwrap_or_default()),
+            verdict.unavailable.to_string(),
+            verdict.cached.to_string(),
+            verdict.held_back.to_string(),
+            verdict.block_number.to_string(),
+        ];
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verdict() -> Verdict {
+        Verdict {
+            id: [1; 32],
+            cid: "QmWt111111111111111111111111111111111111111111".to_string(),
+            signal_amount: 10_u128.pow(24),
+            previous_deny: false,
+            valid: false,
+            deny: true,
+            invalid_kind: Some("ManifestParseError"),
+            reason: Some("manifest parse error: expected \"a\", found b".to_string()),
+            unavailable: false,
+            cached: false,
+            held_back: false,
+            block_number: 1234,
+        }
+    }
+
+    #[test]
+    fn test_json_lines_report() {
+        let report = to_json_lines(&[verdict(), verdict()]).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 2);
+
+        let line: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(line["signal_amount"], "1000000000000000000000000");
+        assert_eq!(line["deny"], true);
+        assert_eq!(line["invalid_kind"], "ManifestParseError");
+        assert_eq!(line["unavailable"], false);
+        assert_eq!(line["cached"], false);
+        assert_eq!(line["held_back"], false);
+        assert_eq!(line["block_number"], 1234);
+    }
+
+    #[test]
+    fn test_csv_report() {
+        let report = to_csv(&[verdict()]);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[1].ends_with(
+            ",false,false,true,ManifestParseError,\"manifest parse error: expected \"\"a\"\", found b\",false,false,false,1234"
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+//! The order in which a reconcile run checks deployments, how many it gets to check and how many
+//! at once, so that a slow IPFS doesn't keep a run from reaching the most relevant deployments.
+
+use crate::network_subgraph::SubgraphDeployment;
+use crate::parse_secs;
+use common::prelude::*;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use structopt::StructOpt;
+use tiny_cid::Cid;
+
+/// Which deployments a run checks first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Priority {
+    /// The highest curation signal first.
+    Signal,
+    /// The longest time since the last check first, never checked deployments before any other.
+    LastCheck,
+}
+
+impl FromStr for Priority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "signal" => Ok(Priority::Signal),
+            "last-check" => Ok(Priority::LastCheck),
+            _ => Err(anyhow!(
+                "invalid priority `{}`, expected `signal` or `last-check`",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(StructOpt, Clone, Copy, Debug)]
+pub struct Schedule {
+    #[structopt(
+        long,
+        env = "ORACLE_PRIORITY",
+        default_value = "signal",
+        help = "The order in which deployments are checked: `signal` checks the highest curation \
+                signal first, and `last-check` the longest time since the last check first. \
+                Deployments left over by the previous run always come first"
+    )]
+    pub priority: Priority,
+
+    #[structopt(
+        long,
+        env = "ORACLE_MAX_CHECKS_PER_RUN",
+        help = "Maximum deployments checked per run, the others are left over for the next run. \
+                Deployments in the valid deployment cache don't count"
+    )]
+    pub max_checks_per_run: Option<usize>,
+
+    #[structopt(
+        long = "max-run-time-secs",
+        env = "ORACLE_MAX_RUN_TIME_SECS",
+        parse(try_from_str = parse_secs),
+        help = "Time after which a run stops starting new checks, the deployments not checked yet \
+                are left over for the next run"
+    )]
+    pub max_run_time: Option<Duration>,
+
+    #[structopt(
+        long,
+        env = "ORACLE_CHECK_CONCURRENCY",
+        default_value = "100",
+        help = "Maximum deployments checked at once. Calls to IPFS are further limited by --ipfs-concurrency"
+    )]
+    pub check_concurrency: usize,
+
+    #[structopt(
+        long = "check-timeout-secs",
+        env = "ORACLE_CHECK_TIMEOUT_SECS",
+        default_value = "0",
+        parse(try_from_str = parse_secs),
+        help = "Time after which the check of a single deployment is abandoned, and the deployment \
+                considered invalid. With the default value of 0 there is no timeout"
+    )]
+    pub check_timeout: Duration,
+}
+
+impl Schedule {
+    /// Sorts the deployments in the order they should be checked. Deployments left over by the
+    /// previous run come first, then the deployments are ordered by priority.
+    pub fn order(
+        &self,
+        deployments: &mut [SubgraphDeployment],
+        left_over: &HashSet<Cid>,
+        last_checked: &HashMap<Cid, SystemTime>,
+    ) {
+        // Stable sorts, so that ties keep the order of the network subgraph.
+        match self.priority {
+            Priority::Signal => deployments
+                .sort_by_key(|d| (!left_over.contains(&d.manifest), Reverse(d.signal_amount))),
+            Priority::LastCheck => deployments.sort_by_key(|d| {
+                (
+                    !left_over.contains(&d.manifest),
+                    last_checked.get(&d.manifest).copied(),
+                )
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::bytes32_to_cid_v0;
+
+    fn deployment(id: u8, signal_amount: u128) -> SubgraphDeployment {
+        SubgraphDeployment {
+            id: [id; 32],
+            manifest: bytes32_to_cid_v0([id; 32]),
+            signal_amount,
+            staked_tokens: 0,
+            created_at: 0,
+            denied_at: 0,
+            deny: false,
+        }
+    }
+
+    fn ids(deployments: &[SubgraphDeployment]) -> Vec<u8> {
+        deployments.iter().map(|d| d.id[0]).collect()
+    }
+
+    fn schedule(priority: Priority) -> Schedule {
+        Schedule {
+            priority,
+            max_checks_per_run: None,
+            max_run_time: None,
+            check_concurrency: 1,
+            check_timeout: Duration::default(),
+        }
+    }
+
+    #[test]
+    fn test_order_by_signal() {
+        let mut deployments = vec![deployment(1, 10), deployment(2, 30), deployment(3, 20)];
+        schedule(Priority::Signal).order(&mut deployments, &HashSet::new(), &HashMap::new());
+        assert_eq!(ids(&deployments), [2, 3, 1]);
+
+        // Left over deployments first.
+        let left_over = HashSet::from([bytes32_to_cid_v0([1; 32])]);
+        schedule(Priority::Signal).order(&mut deployments, &left_over, &HashMap::new());
+        assert_eq!(ids(&deployments), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_order_by_last_check() {
+        let mut deployments = vec![deployment(1, 0), deployment(2, 0), deployment(3, 0)];
+        let last_checked = HashMap::from([
+            (
+                bytes32_to_cid_v0([1; 32]),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(20),
+            ),
+            (
+                bytes32_to_cid_v0([2; 32]),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(10),
+            ),
+        ]);
+        schedule(Priority::LastCheck).order(&mut deployments, &HashSet::new(), &last_checked);
+        // Never checked first.
+        assert_eq!(ids(&deployments), [3, 2, 1]);
+    }
+}
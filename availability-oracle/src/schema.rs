@@ -0,0 +1,303 @@
+//! Semantic checks on the GraphQL schema of a subgraph, for the mistakes that graph-node rejects
+//! when deploying even though the schema parses.
+
+use graphql_parser::schema::{Definition, Document, Field, Type, TypeDefinition, Value};
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// Scalars graph-node provides, on top of the GraphQL built-in ones.
+const SCALARS: &[&str] = &[
+    "ID",
+    "String",
+    "Int",
+    "Float",
+    "Boolean",
+    "BigInt",
+    "BigDecimal",
+    "Bytes",
+    "Int8",
+    "Timestamp",
+];
+
+/// The types an entity `id` may have.
+const ID_TYPES: &[&str] = &["ID", "String", "Bytes", "Int8"];
+
+/// Type names graph-node uses for the types it generates.
+const RESERVED_TYPE_NAMES: &[&str] = &["Query", "Subscription", "Mutation"];
+
+/// The type graph-node reserves for schema-wide declarations, such as full-text search.
+const SCHEMA_TYPE: &str = "_Schema_";
+
+/// A rule broken by the schema, and the type and field that break it.
+#[derive(Debug)]
+pub struct SchemaError {
+    pub type_name: String,
+    pub field: Option<String>,
+    pub reason: String,
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.field {
+            Some(field) => write!(f, "{}.{}: {}", self.type_name, field, self.reason),
+            None => write!(f, "{}: {}", self.type_name, self.reason),
+        }
+    }
+}
+
+/// An object or interface type.
+struct Composite<'a, 'b> {
+    fields: &'b [Field<'a, &'a str>],
+    is_interface: bool,
+}
+
+/// Returns every rule the schema breaks.
+pub fn validate<'a>(schema: &Document<'a, &'a str>) -> Vec<SchemaError> {
+    let mut errors = Vec::new();
+    let mut error = |type_name: &str, field: Option<&str>, reason: String| {
+        errors.push(SchemaError {
+            type_name: type_name.to_string(),
+            field: field.map(str::to_string),
+            reason,
+        })
+    };
+
+    let mut types: HashMap<&str, Option<Composite>> = HashMap::new();
+    for definition in &schema.definitions {
+        let (name, composite) = match definition {
+            Definition::TypeDefinition(TypeDefinition::Object(t)) => (
+                t.name,
+                Some(Composite {
+                    fields: &t.fields,
+                    is_interface: false,
+                }),
+            ),
+            Definition::TypeDefinition(TypeDefinition::Interface(t)) => (
+                t.name,
+                Some(Composite {
+                    fields: &t.fields,
+                    is_interface: true,
+                }),
+            ),
+            Definition::TypeDefinition(TypeDefinition::Enum(t)) => (t.name, None),
+            Definition::TypeDefinition(TypeDefinition::Scalar(t)) => (t.name, None),
+            Definition::TypeDefinition(TypeDefinition::Union(t)) => (t.name, None),
+            Definition::TypeDefinition(TypeDefinition::InputObject(t)) => (t.name, None),
+            _ => continue,
+        };
+        if types.insert(name, composite).is_some() {
+            error(name, None, "defined more than once".to_string());
+        }
+    }
+
+    for definition in &schema.definitions {
+        let (name, fields, directives, interfaces) = match definition {
+            Definition::TypeDefinition(TypeDefinition::Object(t)) => {
+                (t.name, &t.fields, &t.directives, &t.implements_interfaces)
+            }
+            Definition::TypeDefinition(TypeDefinition::Interface(t)) => {
+                (t.name, &t.fields, &t.directives, &t.implements_interfaces)
+            }
+            _ => continue,
+        };
+        if name == SCHEMA_TYPE {
+            continue;
+        }
+        if name.starts_with("__") || RESERVED_TYPE_NAMES.contains(&name) {
+            error(name, None, "reserved type name".to_string());
+        }
+
+        let is_interface = matches!(
+            definition,
+            Definition::TypeDefinition(TypeDefinition::Interface(_))
+        );
+        // Aggregations are entities too, computed by graph-node from a timeseries.
+        if !is_interface
+            && !directives
+                .iter()
+                .any(|d| d.name == "entity" || d.name == "aggregation")
+        {
+            error(name, None, "missing the @entity directive".to_string());
+        }
+
+        // Every entity, and so every interface, needs an id.
+        match fields.iter().find(|field| field.name == "id") {
+            Some(id) if !is_id_type(&id.field_type) => error(
+                name,
+                Some("id"),
+                format!("must be one of {}, and non-null", ID_TYPES.join(", ")),
+            ),
+            Some(_) => (),
+            None => error(name, None, "missing the id field".to_string()),
+        }
+
+        for field in fields {
+            if field.name.starts_with("__") {
+                error(name, Some(field.name), "reserved field name".to_string());
+            }
+
+            let field_type = base_type(&field.field_type);
+            if !SCALARS.contains(&field_type) && !types.contains_key(field_type) {
+                error(
+                    name,
+                    Some(field.name),
+                    format!("unknown type {}", field_type),
+                );
+                continue;
+            }
+
+            if let Some(derived_from) = field.directives.iter().find(|d| d.name == "derivedFrom") {
+                let derived_field =
+                    derived_from
+                        .arguments
+                        .iter()
+                        .find_map(|(arg, value)| match (*arg, value) {
+                            ("field", Value::String(derived_field)) => Some(derived_field),
+                            _ => None,
+                        });
+                let derived_field = match derived_field {
+                    Some(derived_field) => derived_field,
+                    None => {
+                        error(
+                            name,
+                            Some(field.name),
+                            "@derivedFrom without a field argument".to_string(),
+                        );
+                        continue;
+                    }
+                };
+                match types.get(field_type) {
+                    Some(Some(target)) => {
+                        if !target.fields.iter().any(|f| f.name == derived_field) {
+                            error(
+                                name,
+                                Some(field.name),
+                                format!(
+                                    "@derivedFrom field {} is not a field of {}",
+                                    derived_field, field_type
+                                ),
+                            );
+                        }
+                    }
+                    _ => error(
+                        name,
+                        Some(field.name),
+                        format!("@derivedFrom on {}, which is not an entity", field_type),
+                    ),
+                }
+            }
+        }
+
+        for interface in interfaces {
+            match types.get(interface) {
+                Some(Some(Composite {
+                    fields: interface_fields,
+                    is_interface: true,
+                })) => {
+                    for interface_field in interface_fields.iter() {
+                        if !fields.iter().any(|f| f.name == interface_field.name) {
+                            error(
+                                name,
+                                Some(interface_field.name),
+                                format!("missing, but required by interface {}", interface),
+                            );
+                        }
+                    }
+                }
+                _ => error(
+                    name,
+                    None,
+                    format!("implements {}, which is not an interface", interface),
+                ),
+            }
+        }
+    }
+
+    errors
+}
+
+fn is_id_type<'a>(t: &Type<'a, &'a str>) -> bool {
+    match t {
+        Type::NonNullType(t) => matches!(&**t, Type::NamedType(name) if ID_TYPES.contains(name)),
+        _ => false,
+    }
+}
+
+/// The named type within any list and non-null wrappers.
+fn base_type<'a>(t: &Type<'a, &'a str>) -> &'a str {
+    match t {
+        Type::NamedType(name) => name,
+        Type::ListType(t) | Type::NonNullType(t) => base_type(t),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(schema: &str) -> Vec<String> {
+        let schema = graphql_parser::parse_schema::<&str>(schema).unwrap();
+        validate(&schema).iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn test_valid_schema() {
+        assert!(errors(include_str!("test_files/schema.graphql")).is_empty());
+        assert!(errors(
+            r#"
+            interface Named { id: ID!, name: String! }
+            enum Kind { A, B }
+            type Token implements Named @entity {
+                id: Bytes!
+                name: String!
+                kind: Kind
+                owner: Account!
+            }
+            type Account @entity {
+                id: ID!
+                tokens: [Token!]! @derivedFrom(field: "owner")
+            }
+            type Transfer @entity(timeseries: true) {
+                id: Int8!
+                timestamp: Timestamp!
+                amount: BigDecimal!
+            }
+            type Volume @aggregation(intervals: ["hour", "day"], source: "Transfer") {
+                id: Int8!
+                timestamp: Timestamp!
+                total: BigDecimal! @aggregate(fn: "sum", arg: "amount")
+            }
+            "#
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        assert_eq!(
+            errors(
+                r#"
+                interface Named { id: ID!, name: String! }
+                type Token implements Named @entity {
+                    id: Float!
+                    owner: Account!
+                    price: Money
+                }
+                type Account @entity {
+                    tokens: [Token!]! @derivedFrom(field: "holder")
+                }
+                type Query { id: ID! }
+                "#
+            ),
+            [
+                "Token.id: must be one of ID, String, Bytes, Int8, and non-null",
+                "Token.price: unknown type Money",
+                "Token.name: missing, but required by interface Named",
+                "Account: missing the id field",
+                "Account.tokens: @derivedFrom field holder is not a field of Token",
+                "Query: reserved type name",
+                "Query: missing the @entity directive",
+            ]
+        );
+    }
+}
@@ -0,0 +1,290 @@
+use crate::graph_monitoring_subgraph::OracleConfig;
+use crate::hysteresis::DeploymentHistory;
+use common::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use tiny_cid::Cid;
+
+/// State of the oracle that is kept between reconcile runs.
+#[derive(Clone, Default)]
+pub struct OracleState {
+    /// Deployments found valid, with the time they were last validated.
+    pub valid_deployments: Vec<(Cid, SystemTime)>,
+
+    /// Recent check results of the deployments that are failing, or that are denied.
+    pub deployment_history: HashMap<Cid, DeploymentHistory>,
+
+    /// When each deployment was last checked, leaving out valid deployment cache hits.
+    pub last_checked: HashMap<Cid, SystemTime>,
+
+    /// Deployments that the last run did not get to check.
+    pub left_over: HashSet<Cid>,
+
+    /// The config last posted to the DataEdge contract.
+    pub posted_config: Option<OracleConfig>,
+}
+
+/// Persistence for the oracle state, so that it survives restarts.
+pub trait StateStore {
+    /// Load the last saved state. A store that was never saved to loads the default state.
+    fn load(&self) -> Result<OracleState, Error>;
+
+    /// Replace the saved state.
+    fn save(&self, state: &OracleState) -> Result<(), Error>;
+}
+
+/// Keeps nothing, every restart begins with an empty state.
+pub struct NullStateStore;
+
+impl StateStore for NullStateStore {
+    fn load(&self) -> Result<OracleState, Error> {
+        Ok(OracleState::default())
+    }
+
+    fn save(&self, _state: &OracleState) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Stores the state as a JSON file inside a directory.
+pub struct JsonStateStore {
+    dir: PathBuf,
+}
+
+const STATE_FILE: &str = "state.json";
+
+#[derive(Serialize, Deserialize)]
+struct RawValidDeployment {
+    cid: String,
+    // Seconds since the unix epoch.
+    last_validated: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawLastChecked {
+    cid: String,
+    // Seconds since the unix epoch.
+    last_checked: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawDeploymentHistory {
+    cid: String,
+    consecutive_failures: u32,
+    consecutive_successes: u32,
+    // Seconds since the unix epoch.
+    failing_since: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawOracleState {
+    valid_deployments: Vec<RawValidDeployment>,
+    // Absent from state files written by older versions.
+    #[serde(default)]
+    deployment_history: Vec<RawDeploymentHistory>,
+    #[serde(default)]
+    last_checked: Vec<RawLastChecked>,
+    #[serde(default)]
+    left_over: Vec<String>,
+    #[serde(default)]
+    posted_config: Option<OracleConfig>,
+}
+
+fn parse_cid(cid: &str) -> Result<Cid, Error> {
+    Cid::from_str(cid).map_err(|e| anyhow!("bad cid {} in state file: {}", cid, e))
+}
+
+fn to_unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn from_unix_secs(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+impl JsonStateStore {
+    pub fn new(dir: PathBuf) -> Self {
+        JsonStateStore { dir }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(STATE_FILE)
+    }
+}
+
+impl StateStore for JsonStateStore {
+    fn load(&self) -> Result<OracleState, Error> {
+        let path = self.path();
+        if !path.exists() {
+            return Ok(OracleState::default());
+        }
+
+        let raw: RawOracleState = serde_json::from_slice(&fs::read(&path)?)
+            .with_context(|| format!("failed to parse state file {}", path.display()))?;
+
+        let valid_deployments = raw
+            .valid_deployments
+            .into_iter()
+            .map(|entry| Ok((parse_cid(&entry.cid)?, from_unix_secs(entry.last_validated))))
+            .collect::<Result<_, Error>>()?;
+
+        let deployment_history = raw
+            .deployment_history
+            .into_iter()
+            .map(|entry| {
+                let history = DeploymentHistory {
+                    consecutive_failures: entry.consecutive_failures,
+                    consecutive_successes: entry.consecutive_successes,
+                    failing_since: entry.failing_since.map(from_unix_secs),
+                };
+                Ok((parse_cid(&entry.cid)?, history))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let last_checked = raw
+            .last_checked
+            .into_iter()
+            .map(|entry| Ok((parse_cid(&entry.cid)?, from_unix_secs(entry.last_checked))))
+            .collect::<Result<_, Error>>()?;
+
+        let left_over = raw
+            .left_over
+            .iter()
+            .map(|cid| parse_cid(cid))
+            .collect::<Result<_, Error>>()?;
+
+        Ok(OracleState {
+            valid_deployments,
+            deployment_history,
+            last_checked,
+            left_over,
+            posted_config: raw.posted_config,
+        })
+    }
+
+    fn save(&self, state: &OracleState) -> Result<(), Error> {
+        let raw = RawOracleState {
+            valid_deployments: state
+                .valid_deployments
+                .iter()
+                .map(|(cid, last_validated)| RawValidDeployment {
+                    cid: cid.to_string(),
+                    last_validated: to_unix_secs(*last_validated),
+                })
+                .collect(),
+            deployment_history: state
+                .deployment_history
+                .iter()
+                .map(|(cid, history)| RawDeploymentHistory {
+                    cid: cid.to_string(),
+                    consecutive_failures: history.consecutive_failures,
+                    consecutive_successes: history.consecutive_successes,
+                    failing_since: history.failing_since.map(to_unix_secs),
+                })
+                .collect(),
+            last_checked: state
+                .last_checked
+                .iter()
+                .map(|(cid, last_checked)| RawLastChecked {
+                    cid: cid.to_string(),
+                    last_checked: to_unix_secs(*last_checked),
+                })
+                .collect(),
+            left_over: state.left_over.iter().map(|cid| cid.to_string()).collect(),
+            posted_config: state.posted_config.clone(),
+        };
+
+        // Write to a temporary file first so that a crash never leaves a half written state.
+        fs::create_dir_all(&self.dir)?;
+        let tmp_path = self.dir.join(format!("{}.tmp", STATE_FILE));
+        fs::write(&tmp_path, serde_json::to_vec(&raw)?)?;
+        fs::rename(&tmp_path, self.path())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CID: &str = "QmWt111111111111111111111111111111111111111111";
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "availability-oracle-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_json_state_store_roundtrip() {
+        let dir = test_dir("roundtrip");
+        let store = JsonStateStore::new(dir.clone());
+
+        // Nothing saved yet.
+        assert!(store.load().unwrap().valid_deployments.is_empty());
+
+        let last_validated = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let history = DeploymentHistory {
+            consecutive_failures: 2,
+            consecutive_successes: 0,
+            failing_since: Some(last_validated),
+        };
+        let state = OracleState {
+            valid_deployments: vec![(Cid::from_str(CID).unwrap(), last_validated)],
+            deployment_history: HashMap::from([(Cid::from_str(CID).unwrap(), history.clone())]),
+            last_checked: HashMap::from([(Cid::from_str(CID).unwrap(), last_validated)]),
+            left_over: HashSet::from([Cid::from_str(CID).unwrap()]),
+            posted_config: Some(OracleConfig {
+                version: "v1.0.0".to_string(),
+                ipfs_concurrency: "10".to_string(),
+                ipfs_timeout: "30000".to_string(),
+                min_signal: "100".to_string(),
+                period: "60".to_string(),
+                grace_period: "10".to_string(),
+                supported_data_source_kinds: "ethereum".to_string(),
+                network_subgraph_deployment_id: "Qm123".to_string(),
+                epoch_block_oracle_subgraph_deployment_id: "Qm456".to_string(),
+                subgraph_availability_manager_contract: "0x123".to_string(),
+                oracle_index: "0".to_string(),
+                max_file_sizes: "manifest:1024,schema:1024,abi:1024,wasm:2048".to_string(),
+                version_bounds: "spec:0.0.2-1.3.0,api:0.0.2-0.0.9".to_string(),
+            }),
+        };
+        store.save(&state).unwrap();
+
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.valid_deployments.len(), 1);
+        assert_eq!(loaded.valid_deployments[0].0.to_string(), CID);
+        assert_eq!(loaded.valid_deployments[0].1, last_validated);
+        assert_eq!(
+            loaded.deployment_history.get(&Cid::from_str(CID).unwrap()),
+            Some(&history)
+        );
+        assert_eq!(loaded.last_checked.len(), 1);
+        assert_eq!(loaded.left_over.len(), 1);
+        assert_eq!(loaded.posted_config, state.posted_config);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_state_store_corrupt_file() {
+        let dir = test_dir("corrupt");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(STATE_FILE), "{ not json").unwrap();
+
+        assert!(JsonStateStore::new(dir.clone()).load().is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
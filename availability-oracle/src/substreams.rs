@@ -0,0 +1,159 @@
+//! Checks on the package of a substreams data source, an `sf.substreams.v1.Package` protobuf.
+//! Only the module definitions are decoded.
+
+use crate::proto::{fields, Field};
+use crate::Invalid;
+use common::prelude::*;
+
+/// Output types of a module whose entity changes graph-node can apply directly.
+const ENTITY_CHANGES_TYPES: &[&str] = &[
+    "proto:sf.substreams.sink.entity.v1.EntityChanges",
+    "proto:substreams.entity.v1.EntityChanges",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum ModuleKind {
+    Map,
+    Store,
+    Other,
+}
+
+#[derive(Debug)]
+pub struct Module {
+    pub name: String,
+    pub kind: ModuleKind,
+    pub output_type: Option<String>,
+}
+
+/// Checks that the package has a `module_name` map module, with an output graph-node can use.
+/// Without a mapping handler, the output must be entity changes.
+pub fn check_package(package: &[u8], module_name: &str, has_handler: bool) -> Result<(), Invalid> {
+    let modules = decode_modules(package).map_err(Invalid::SubstreamsPackageParseError)?;
+    let module = modules
+        .iter()
+        .find(|module| module.name == module_name)
+        .ok_or_else(|| Invalid::SubstreamsModuleNotFound(module_name.to_string()))?;
+
+    let output_type = module.output_type.as_deref().unwrap_or_default();
+    let compatible = module.kind == ModuleKind::Map
+        && !output_type.is_empty()
+        && (has_handler || ENTITY_CHANGES_TYPES.contains(&output_type));
+    if !compatible {
+        return Err(Invalid::SubstreamsModuleOutputType(
+            module_name.to_string(),
+            match module.kind {
+                ModuleKind::Map => output_type.to_string(),
+                ModuleKind::Store => "a store module".to_string(),
+                ModuleKind::Other => "not a map module".to_string(),
+            },
+        ));
+    }
+    Ok(())
+}
+
+/// Decodes the modules of a package, `Package.modules.modules`.
+pub fn decode_modules(package: &[u8]) -> Result<Vec<Module>, Error> {
+    let mut modules = Vec::new();
+    // Package { modules: Modules = 6 }
+    for modules_message in bytes_fields(package, 6)? {
+        // Modules { modules: repeated Module = 1 }
+        for module in bytes_fields(modules_message, 1)? {
+            modules.push(decode_module(module)?);
+        }
+    }
+    Ok(modules)
+}
+
+fn decode_module(module: &[u8]) -> Result<Module, Error> {
+    let mut name = String::new();
+    let mut kind = ModuleKind::Other;
+    let mut output_type = None;
+
+    for field in fields(module) {
+        match field? {
+            (1, Field::Bytes(n)) => name = String::from_utf8(n.to_vec())?,
+            // KindMap { output_type: string = 1 }
+            (2, Field::Bytes(kind_map)) => {
+                kind = ModuleKind::Map;
+                if let Some(t) = bytes_fields(kind_map, 1)?.pop() {
+                    output_type = Some(String::from_utf8_lossy(t).into_owned());
+                }
+            }
+            (3, Field::Bytes(_)) => kind = ModuleKind::Store,
+            // Output { type: string = 1 }, which takes precedence over the KindMap output type.
+            // The inputs, field 6, are skipped.
+            (7, Field::Bytes(output)) => {
+                if let Some(t) = bytes_fields(output, 1)?.pop() {
+                    output_type = Some(String::from_utf8_lossy(t).into_owned());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Module {
+        name,
+        kind,
+        output_type,
+    })
+}
+
+/// The values of the length delimited field `number` in `message`.
+fn bytes_fields(message: &[u8], number: u64) -> Result<Vec<&[u8]>, Error> {
+    let mut values = Vec::new();
+    for field in fields(message) {
+        if let (field, Field::Bytes(value)) = field? {
+            if field == number {
+                values.push(value);
+            }
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKAGE: &[u8] = include_bytes!("test_files/substreams.spkg");
+
+    #[test]
+    fn test_decode_modules() {
+        let modules = decode_modules(PACKAGE).unwrap();
+        let names: Vec<&str> = modules.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["graph_out", "map_events", "store_totals"]);
+        assert_eq!(modules[2].kind, ModuleKind::Store);
+        assert_eq!(
+            modules[0].output_type.as_deref(),
+            Some(ENTITY_CHANGES_TYPES[0])
+        );
+        // Has a `sf.substreams.v1.Clock` source input, which must not be taken for its output.
+        assert_eq!(
+            modules[1].output_type.as_deref(),
+            Some("proto:acme.v1.Events")
+        );
+        assert_eq!(modules[2].output_type, None);
+    }
+
+    #[test]
+    fn test_check_package() {
+        assert!(check_package(PACKAGE, "graph_out", false).is_ok());
+        assert!(check_package(PACKAGE, "map_events", true).is_ok());
+        assert!(matches!(
+            check_package(PACKAGE, "map_events", false),
+            Err(Invalid::SubstreamsModuleOutputType(..))
+        ));
+        assert!(matches!(
+            check_package(PACKAGE, "store_totals", true),
+            Err(Invalid::SubstreamsModuleOutputType(..))
+        ));
+        assert!(matches!(
+            check_package(PACKAGE, "graph_in", false),
+            Err(Invalid::SubstreamsModuleNotFound(_))
+        ));
+        assert!(matches!(
+            check_package(b"\xff\xff", "graph_out", false),
+            Err(Invalid::SubstreamsPackageParseError(_))
+        ));
+    }
+}
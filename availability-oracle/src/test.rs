@@ -1,16 +1,25 @@
 #[cfg(test)]
 mod tests {
+    use crate::arweave::*;
+    use crate::circuit_breaker::CircuitBreaker;
     use crate::contract;
     use crate::epoch_block_oracle_subgraph::*;
+    use crate::hysteresis::Hysteresis;
     use crate::ipfs::*;
     use crate::network_subgraph::*;
+    use crate::report::{NullReportWriter, ReportWriter, Verdict};
+    use crate::schedule::{Priority, Schedule};
+    use crate::state_store::OracleState;
     use crate::util::bytes32_to_cid_v0;
-    use crate::util::cid_v0_to_bytes32;
+    use crate::util::cid_to_bytes32;
+    use crate::{CheckError, FileSizeLimits, Invalid, RunSettings, Sources, VersionBounds};
     use async_trait::async_trait;
     use bytes::Bytes;
     use common::prelude::*;
     use futures::Stream;
-    use std::sync::Arc;
+    use semver::Version;
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
     use std::time::Duration;
     use std::{pin::Pin, str::FromStr};
     use tiny_cid::Cid;
@@ -25,6 +34,7 @@ mod tests {
     const SEVEN: &str = "QmWt777777777777777777777777777777777777777777";
     const SUBSTREAM: &str = "QmWt888888888888888888888888888888888888888888";
     const FILE_DS: &str = "QmWt999999999999999999999999999999999999999999";
+    const SUBSTREAM_PACKAGE: &str = "QmWt3substreamzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
 
     const UNAVAILABLE_LINK: &str = "QmWt3unavzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
 
@@ -32,6 +42,12 @@ mod tests {
     const VALID_ABI: &str = "QmWt3abizzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
     const INVALID_ABI: &str = "QmWt3badAbizzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
     const VALID_SCHEMA: &str = "QmWt3schemazzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+    const VALID_PACKAGE: &str = "QmWt3spkgzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+
+    const ARWEAVE_DS: &str = "QmWt3arweavezzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+    const ARWEAVE_DS_UNAVAILABLE: &str = "QmWt3arUnavzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+    const ARWEAVE_TX: &str = "Yk8Pbd3mLfMp0UHxRQ-BaQXkJhGdzcEDqWnzhh4JQsI";
+    const UNAVAILABLE_TX: &str = "unavailablezzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
 
     // Test the reconcile logic, mocking the data. Test subgraphs:
     // - ZERO - remains invalid
@@ -42,44 +58,604 @@ mod tests {
     // - FIVE - becomes invalid due to invalid ABI
     // - SIX - becomes invalid due to invalid manifest
     // - SEVEN - becomes invalid due to non-mainnet network
-    // - SUBSTREAM - becomes invalid due to `kind: substream`
+    // - SUBSTREAM - becomes invalid due to the bad CID of its package
     // - FILE_DS - remains valid
+    // - SUBSTREAM_PACKAGE - remains valid, with a package that decodes
+
+    fn version_bounds() -> VersionBounds {
+        VersionBounds {
+            min_spec_version: Version::new(0, 0, 2),
+            max_spec_version: Version::new(1, 3, 0),
+            min_api_version: Version::new(0, 0, 2),
+            max_api_version: Version::new(0, 0, 9),
+        }
+    }
+
+    fn file_size_limits() -> FileSizeLimits {
+        FileSizeLimits {
+            manifest: 1024,
+            schema: 1024,
+            abi: 1024,
+            wasm: 1024,
+            file_source: 1024,
+        }
+    }
+
+    /// The settings of the reconcile tests, checking `ethereum/contract` data sources only.
+    fn settings() -> RunSettings {
+        RunSettings {
+            min_signal: 0,
+            max_network_subgraph_lag: 10,
+            grace_period: Duration::default(),
+            supported_ds_kinds: vec!["ethereum/contract".into()],
+            supported_features: vec![],
+            forbidden_host_fns: vec!["ipfs.*".into()],
+            file_size_limits: file_size_limits(),
+            version_bounds: version_bounds(),
+            hysteresis: hysteresis(),
+            circuit_breaker: CircuitBreaker::default(),
+            schedule: schedule(),
+        }
+    }
+
+    #[test]
+    fn test_version_bounds() {
+        let bounds = version_bounds();
+        assert!(bounds.spec_version(&Version::new(0, 0, 5)).is_ok());
+        assert!(bounds.spec_version(&Version::new(1, 3, 0)).is_ok());
+        assert!(matches!(
+            bounds.spec_version(&Version::new(0, 0, 1)),
+            Err(Invalid::UnsupportedSpecVersion(_))
+        ));
+        assert!(matches!(
+            bounds.api_version(&Version::new(0, 0, 10)),
+            Err(Invalid::UnsupportedApiVersion(_))
+        ));
+        assert_eq!(bounds.to_string(), "spec:0.0.2-1.3.0,api:0.0.2-0.0.9");
+    }
 
     #[tokio::test]
     async fn test_reconcile() {
+        reconcile(&MockStateManager, &CircuitBreaker::default())
+            .await
+            .unwrap();
+    }
+
+    // With the circuit breaker tripped, the new denials are held back but FOUR is still un-denied.
+    #[tokio::test]
+    async fn test_reconcile_circuit_breaker() {
+        let circuit_breaker = CircuitBreaker {
+            max_new_denials: Some(1),
+            ..Default::default()
+        };
+        reconcile(&MockUndenyStateManager, &circuit_breaker)
+            .await
+            .unwrap();
+    }
+
+    async fn reconcile(
+        state_manager: &dyn contract::StateManager,
+        circuit_breaker: &CircuitBreaker,
+    ) -> Result<OracleState, Error> {
         crate::reconcile_deny_list(
             &common::logging::create_logger(),
-            &MockIpfs,
-            &MockStateManager,
-            Arc::new(MockSubgraph),
-            0,
-            Duration::default(),
-            Arc::new(MockEBOSubgraph),
-            &vec![
-                "ethereum".into(),
-                "ethereum/contract".into(),
-                "file/ipfs".into(),
-                "substreams".into(),
-            ],
-            vec![],
+            Sources {
+                ipfs: &MockIpfs,
+                arweave: &MockArweave,
+                chain_head: &MockChainHead,
+                subgraph: Arc::new(MockSubgraph),
+                epoch_subgraph: Arc::new(MockEBOSubgraph),
+            },
+            state_manager,
+            &RunSettings {
+                supported_ds_kinds: vec![
+                    "ethereum".into(),
+                    "ethereum/contract".into(),
+                    "file/ipfs".into(),
+                    "substreams".into(),
+                ],
+                circuit_breaker: *circuit_breaker,
+                ..settings()
+            },
+            OracleState::default(),
+            &MockReportWriter {
+                // The limits of `circuit_breaker` are only ever set low enough to trip it.
+                held_back: circuit_breaker.max_new_denials.is_some(),
+            },
+        )
+        .await
+    }
+
+    // A denied deployment is checked in every run, rather than taken from the valid deployment
+    // cache, and only un-denied once it passed the checks in `undeny_after_runs` runs.
+    #[tokio::test]
+    async fn test_reconcile_undeny_after_runs() {
+        let ipfs = RecordingIpfs::default();
+        let state_manager = RecordingStateManager::default();
+        let hysteresis = Hysteresis {
+            deny_after_runs: 1,
+            deny_after: Duration::default(),
+            undeny_after_runs: 3,
+        };
+        let mut state = OracleState::default();
+        for run in 0..3 {
+            state = reconcile_run(
+                &ipfs,
+                &state_manager,
+                Arc::new(MockDeployments(vec![(FOUR, true, 0)])),
+                &hysteresis,
+                &schedule(),
+                state,
+                &NullReportWriter,
+            )
+            .await
+            .unwrap();
+            // Only cached once un-denied.
+            assert_eq!(state.valid_deployments.is_empty(), run < 2);
+        }
+
+        assert_eq!(ipfs.fetched(FOUR), 3);
+        assert_eq!(
+            state_manager.calls(),
+            vec![vec![], vec![], vec![(FOUR.to_string(), false)]]
+        );
+    }
+
+    // Deployments beyond `max_checks_per_run` get no verdict, aren't denied, and go first in the
+    // next run, even before deployments with a higher signal.
+    #[tokio::test]
+    async fn test_reconcile_max_checks_per_run() {
+        let ipfs = RecordingIpfs::default();
+        let state_manager = RecordingStateManager::default();
+        let report_writer = RecordingReportWriter::default();
+        let subgraph = Arc::new(MockDeployments(vec![
+            (TWO, false, 30),
+            (THREE, false, 20),
+            (ONE, false, 10),
+        ]));
+        let schedule = Schedule {
+            max_checks_per_run: Some(1),
+            ..schedule()
+        };
+
+        let mut state = OracleState::default();
+        for _ in 0..2 {
+            state = reconcile_run(
+                &ipfs,
+                &state_manager,
+                subgraph.clone(),
+                &hysteresis(),
+                &schedule,
+                state,
+                &report_writer,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(report_writer.cids(), vec![vec![TWO], vec![THREE]]);
+        assert_eq!(
+            state_manager.calls(),
+            vec![
+                vec![(TWO.to_string(), true)],
+                vec![(THREE.to_string(), true)]
+            ]
+        );
+        assert_eq!(ipfs.fetched(ONE), 0);
+        assert_eq!(
+            state.left_over,
+            HashSet::from([Cid::from_str(ONE).unwrap(), Cid::from_str(TWO).unwrap()])
+        );
+    }
+
+    // Once `max_run_time` is up no check is started, the deployments are all left over.
+    #[tokio::test]
+    async fn test_reconcile_max_run_time() {
+        let ipfs = RecordingIpfs::default();
+        let state_manager = RecordingStateManager::default();
+        let report_writer = RecordingReportWriter::default();
+        let subgraph = Arc::new(MockDeployments(vec![(TWO, false, 0), (ONE, false, 0)]));
+
+        let state = reconcile_run(
+            &ipfs,
+            &state_manager,
+            subgraph.clone(),
+            &hysteresis(),
+            &Schedule {
+                max_run_time: Some(Duration::ZERO),
+                ..schedule()
+            },
+            OracleState::default(),
+            &report_writer,
         )
         .await
         .unwrap();
+        assert_eq!(report_writer.cids(), vec![Vec::<String>::new()]);
+        assert_eq!(state_manager.calls(), vec![vec![]]);
+        assert_eq!(state.left_over.len(), 2);
+        assert!(ipfs.0.lock().unwrap().is_empty());
+
+        let state = reconcile_run(
+            &ipfs,
+            &state_manager,
+            subgraph,
+            &hysteresis(),
+            &schedule(),
+            state,
+            &report_writer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report_writer.cids()[1], vec![TWO, ONE]);
+        assert!(state.left_over.is_empty());
+    }
+
+    fn hysteresis() -> Hysteresis {
+        Hysteresis {
+            deny_after_runs: 1,
+            deny_after: Duration::default(),
+            undeny_after_runs: 1,
+        }
+    }
+
+    /// Records the verdicts of each run.
+    #[derive(Default)]
+    struct RecordingReportWriter(Mutex<Vec<Vec<Verdict>>>);
+
+    impl RecordingReportWriter {
+        fn cids(&self) -> Vec<Vec<String>> {
+            let runs = self.0.lock().unwrap();
+            runs.iter()
+                .map(|verdicts| verdicts.iter().map(|v| v.cid.clone()).collect())
+                .collect()
+        }
+    }
+
+    impl ReportWriter for RecordingReportWriter {
+        fn write(&self, verdicts: &[Verdict]) -> Result<(), Error> {
+            self.0.lock().unwrap().push(verdicts.to_vec());
+            Ok(())
+        }
+    }
+
+    // A check that runs past `check_timeout` is a failed check, not a valid deployment and not
+    // an unavailable file, so that a hanging deployment can't trip the circuit breaker.
+    #[tokio::test]
+    async fn test_reconcile_check_timeout() {
+        let state_manager = RecordingStateManager::default();
+        let report_writer = RecordingReportWriter::default();
+
+        let state = reconcile_run(
+            &HangingIpfs,
+            &state_manager,
+            Arc::new(MockDeployments(vec![(ONE, false, 0)])),
+            &hysteresis(),
+            &Schedule {
+                check_timeout: Duration::from_millis(50),
+                ..schedule()
+            },
+            OracleState::default(),
+            &report_writer,
+        )
+        .await
+        .unwrap();
+
+        let verdicts = report_writer.0.lock().unwrap();
+        let verdict = &verdicts[0][0];
+        assert!(!verdict.valid);
+        assert!(!verdict.unavailable);
+        assert_eq!(verdict.invalid_kind, Some("CheckTimeout"));
+        let one = Cid::from_str(ONE).unwrap();
+        assert_eq!(state.deployment_history[&one].consecutive_failures, 1);
+        assert!(state.valid_deployments.is_empty());
+        assert_eq!(state_manager.calls(), vec![vec![(ONE.to_string(), true)]]);
+    }
+
+    /// Never serves a file.
+    struct HangingIpfs;
+
+    #[async_trait]
+    impl Ipfs for HangingIpfs {
+        async fn cat(&self, _cid: Cid, _max_size: u64) -> Result<Bytes, IpfsError> {
+            futures::future::pending().await
+        }
+
+        fn invalidate_cache(&self) {
+            unreachable!("invalidate cache");
+        }
+    }
+
+    // A run refuses to proceed on a network subgraph with indexing errors, or one lagging the
+    // chain head by more than the maximum, without submitting anything.
+    #[tokio::test]
+    async fn test_reconcile_unhealthy_network_subgraph() {
+        let meta = |block_number, has_indexing_errors| NetworkSubgraphMeta {
+            block_number,
+            block_hash: None,
+            deployment: "QmNetworkSubgraph".to_string(),
+            has_indexing_errors,
+        };
+        for meta in [meta(BLOCK, true), meta(BLOCK - 6, false)] {
+            let state_manager = RecordingStateManager::default();
+            let res = reconcile_run(
+                &MockIpfs,
+                &state_manager,
+                Arc::new(MockUnhealthySubgraph(meta)),
+                &hysteresis(),
+                &schedule(),
+                OracleState::default(),
+                &NullReportWriter,
+            )
+            .await;
+            assert!(res.is_err());
+            assert!(state_manager.calls().is_empty());
+        }
+    }
+
+    struct MockUnhealthySubgraph(NetworkSubgraphMeta);
+
+    #[async_trait]
+    impl NetworkSubgraph for MockUnhealthySubgraph {
+        async fn meta(&self) -> Result<NetworkSubgraphMeta, Error> {
+            Ok(self.0.clone())
+        }
+
+        fn deployments_over_threshold(
+            self: Arc<Self>,
+            _curation_threshold: u64,
+            _grace_period: Duration,
+            _block: u64,
+        ) -> Pin<Box<dyn Stream<Item = Result<SubgraphDeployment, Error>>>> {
+            unreachable!("deployments of an unhealthy network subgraph");
+        }
+    }
+
+    fn schedule() -> Schedule {
+        Schedule {
+            priority: Priority::Signal,
+            max_checks_per_run: None,
+            max_run_time: None,
+            check_concurrency: 100,
+            check_timeout: Duration::default(),
+        }
+    }
+
+    async fn reconcile_run(
+        ipfs: &impl Ipfs,
+        state_manager: &dyn contract::StateManager,
+        subgraph: Arc<impl NetworkSubgraph>,
+        hysteresis: &Hysteresis,
+        schedule: &Schedule,
+        state: OracleState,
+        report_writer: &dyn ReportWriter,
+    ) -> Result<OracleState, Error> {
+        crate::reconcile_deny_list(
+            &common::logging::create_logger(),
+            Sources {
+                ipfs,
+                arweave: &MockArweave,
+                chain_head: &MockChainHead,
+                subgraph,
+                epoch_subgraph: Arc::new(MockEBOSubgraph),
+            },
+            state_manager,
+            &RunSettings {
+                hysteresis: *hysteresis,
+                schedule: *schedule,
+                ..settings()
+            },
+            state,
+            report_writer,
+        )
+        .await
+    }
+
+    /// Serves the given deployments of `MockSubgraph`, with their deny flag and signal, in order.
+    struct MockDeployments(Vec<(&'static str, bool, u128)>);
+
+    #[async_trait]
+    impl NetworkSubgraph for MockDeployments {
+        async fn meta(&self) -> Result<NetworkSubgraphMeta, Error> {
+            MockSubgraph.meta().await
+        }
+
+        fn deployments_over_threshold(
+            self: Arc<Self>,
+            _curation_threshold: u64,
+            _grace_period: Duration,
+            _block: u64,
+        ) -> Pin<Box<dyn Stream<Item = Result<SubgraphDeployment, Error>>>> {
+            let deployments = self
+                .0
+                .iter()
+                .map(|(id, deny, signal_amount)| {
+                    let manifest = Cid::from_str(id).unwrap();
+                    Ok(SubgraphDeployment {
+                        id: cid_to_bytes32(&manifest).unwrap(),
+                        manifest,
+                        signal_amount: *signal_amount,
+                        staked_tokens: 0,
+                        created_at: 0,
+                        denied_at: if *deny { 1 } else { 0 },
+                        deny: *deny,
+                    })
+                })
+                .collect::<Vec<_>>();
+            futures::stream::iter(deployments).boxed()
+        }
+    }
+
+    /// Serves the files of `MockIpfs`, recording which were fetched.
+    #[derive(Default)]
+    struct RecordingIpfs(Mutex<Vec<String>>);
+
+    impl RecordingIpfs {
+        fn fetched(&self, cid: &str) -> usize {
+            self.0.lock().unwrap().iter().filter(|c| *c == cid).count()
+        }
+    }
+
+    #[async_trait]
+    impl Ipfs for RecordingIpfs {
+        async fn cat(&self, cid: Cid, max_size: u64) -> Result<Bytes, IpfsError> {
+            self.0.lock().unwrap().push(cid.to_string());
+            MockIpfs.cat(cid, max_size).await
+        }
+
+        fn invalidate_cache(&self) {
+            unreachable!("invalidate cache");
+        }
+    }
+
+    /// Records the changes submitted by each `deny_many` call.
+    #[derive(Default)]
+    struct RecordingStateManager(Mutex<Vec<Vec<(String, bool)>>>);
+
+    impl RecordingStateManager {
+        fn calls(&self) -> Vec<Vec<(String, bool)>> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl contract::StateManager for RecordingStateManager {
+        async fn deny_many(&self, denied_status: Vec<([u8; 32], bool)>) -> Result<(), Error> {
+            let denied_status = denied_status
+                .into_iter()
+                .map(|(id, deny)| (bytes32_to_cid_v0(id).to_string(), deny))
+                .collect();
+            self.0.lock().unwrap().push(denied_status);
+            Ok(())
+        }
+    }
+
+    // The state of a run is keyed by the manifest CIDs of the network subgraph, so that the next
+    // run finds it for deployments with a CIDv1 `ipfsHash` too. ONE stays valid and TWO fails in
+    // both runs, without being denied yet.
+    #[tokio::test]
+    async fn test_reconcile_cid_v1() {
+        let mut state = OracleState::default();
+        for _ in 0..2 {
+            state = crate::reconcile_deny_list(
+                &common::logging::create_logger(),
+                Sources {
+                    ipfs: &MockV1Ipfs,
+                    arweave: &MockArweave,
+                    chain_head: &MockChainHead,
+                    subgraph: Arc::new(MockV1Subgraph),
+                    epoch_subgraph: Arc::new(MockEBOSubgraph),
+                },
+                &contract::StateManagerDryRun::new(common::logging::create_logger()),
+                &RunSettings {
+                    hysteresis: Hysteresis {
+                        deny_after_runs: 3,
+                        ..hysteresis()
+                    },
+                    ..settings()
+                },
+                state,
+                &NullReportWriter,
+            )
+            .await
+            .unwrap();
+        }
+
+        let (one, two) = (cid_v1(ONE), cid_v1(TWO));
+        assert_eq!(state.valid_deployments.len(), 1);
+        assert_eq!(state.valid_deployments[0].0, one);
+        assert_eq!(state.deployment_history[&two].consecutive_failures, 2);
+        assert!(state.last_checked.contains_key(&two));
+    }
+
+    fn cid_v1(cid: &str) -> Cid {
+        Cid::new_v1(tiny_cid::DAG_PROTOBUF, *Cid::from_str(cid).unwrap().hash())
+    }
+
+    struct MockV1Subgraph;
+
+    #[async_trait]
+    impl NetworkSubgraph for MockV1Subgraph {
+        async fn meta(&self) -> Result<NetworkSubgraphMeta, Error> {
+            MockSubgraph.meta().await
+        }
+
+        fn deployments_over_threshold(
+            self: Arc<Self>,
+            _curation_threshold: u64,
+            _grace_period: Duration,
+            _block: u64,
+        ) -> Pin<Box<dyn Stream<Item = Result<SubgraphDeployment, Error>>>> {
+            let new_subgraph = |id| {
+                let manifest = cid_v1(id);
+                Ok(SubgraphDeployment {
+                    id: cid_to_bytes32(&manifest).unwrap(),
+                    manifest,
+                    signal_amount: 0,
+                    staked_tokens: 0,
+                    created_at: 0,
+                    denied_at: 0,
+                    deny: false,
+                })
+            };
+            futures::stream::iter(vec![new_subgraph(ONE), new_subgraph(TWO)]).boxed()
+        }
+    }
+
+    /// Serves the files of `MockIpfs` under their CIDv1 too.
+    struct MockV1Ipfs;
+
+    #[async_trait]
+    impl Ipfs for MockV1Ipfs {
+        async fn cat(&self, cid: Cid, max_size: u64) -> Result<Bytes, IpfsError> {
+            let v0 = bytes32_to_cid_v0(cid_to_bytes32(&cid).unwrap());
+            MockIpfs.cat(v0, max_size).await
+        }
+
+        fn invalidate_cache(&self) {
+            unreachable!("invalidate cache");
+        }
+    }
+
+    const BLOCK: u64 = 1000;
+
+    struct MockChainHead;
+
+    #[async_trait]
+    impl contract::ChainHead for MockChainHead {
+        async fn block_number(&self) -> Result<u64, Error> {
+            Ok(BLOCK + 5)
+        }
     }
 
     struct MockSubgraph;
 
+    #[async_trait]
     impl NetworkSubgraph for MockSubgraph {
+        async fn meta(&self) -> Result<NetworkSubgraphMeta, Error> {
+            Ok(NetworkSubgraphMeta {
+                block_number: BLOCK,
+                block_hash: None,
+                deployment: "QmNetworkSubgraph".to_string(),
+                has_indexing_errors: false,
+            })
+        }
+
         fn deployments_over_threshold(
             self: Arc<Self>,
             _curation_threshold: u64,
             _grace_period: Duration,
+            block: u64,
         ) -> Pin<Box<dyn Stream<Item = Result<SubgraphDeployment, Error>>>> {
+            assert_eq!(block, BLOCK);
             let new_subgraph = |id, deny| {
-                let id = cid_v0_to_bytes32(&Cid::from_str(id).unwrap());
+                let manifest = Cid::from_str(id).unwrap();
                 Ok(SubgraphDeployment {
-                    id,
+                    id: cid_to_bytes32(&manifest).unwrap(),
+                    manifest,
                     signal_amount: 0,
+                    staked_tokens: 0,
+                    created_at: 0,
+                    denied_at: if deny { 1 } else { 0 },
                     deny,
                 })
             };
@@ -94,6 +670,7 @@ mod tests {
                 new_subgraph(SEVEN, false),
                 new_subgraph(SUBSTREAM, false),
                 new_subgraph(FILE_DS, false),
+                new_subgraph(SUBSTREAM_PACKAGE, false),
             ])
             .boxed()
         }
@@ -114,7 +691,7 @@ mod tests {
     #[async_trait]
     impl Ipfs for MockIpfs {
         /// Download a file.
-        async fn cat(&self, cid: Cid) -> Result<Bytes, IpfsError> {
+        async fn cat(&self, cid: Cid, _max_size: u64) -> Result<Bytes, IpfsError> {
             let valid_manifest = include_bytes!("test_files/valid.yaml").to_vec().into();
             match cid.to_string().as_str() {
                 ZERO => Err(IpfsError::ClientTimeout(cid, Error::msg("zero"))),
@@ -127,12 +704,20 @@ mod tests {
                 SEVEN => Ok(include_bytes!("test_files/seven.yaml").to_vec().into()),
                 SUBSTREAM => Ok(include_bytes!("test_files/substream.yaml").to_vec().into()),
                 FILE_DS => Ok(include_bytes!("test_files/file_ds.yaml").to_vec().into()),
+                SUBSTREAM_PACKAGE => Ok(include_bytes!("test_files/substream_package.yaml")
+                    .to_vec()
+                    .into()),
+                ARWEAVE_DS => Ok(include_bytes!("test_files/arweave_ds.yaml").to_vec().into()),
+                ARWEAVE_DS_UNAVAILABLE => Ok(include_str!("test_files/arweave_ds.yaml")
+                    .replace(ARWEAVE_TX, UNAVAILABLE_TX)
+                    .into()),
 
                 UNAVAILABLE_LINK => Err(IpfsError::ClientTimeout(cid, Error::msg("unavail"))),
 
                 VALID_WASM => Ok(include_bytes!("test_files/Contract.wasm").to_vec().into()),
                 VALID_ABI => Ok(include_bytes!("test_files/Contract.abi").to_vec().into()),
                 VALID_SCHEMA => Ok(include_bytes!("test_files/schema.graphql").to_vec().into()),
+                VALID_PACKAGE => Ok(include_bytes!("test_files/substreams.spkg").to_vec().into()),
                 INVALID_ABI => Ok(include_bytes!("test_files/BadContract.abi").to_vec().into()),
 
                 _ => unreachable!("unknown cid"),
@@ -144,6 +729,175 @@ mod tests {
         }
     }
 
+    struct MockArweave;
+
+    #[async_trait]
+    impl Arweave for MockArweave {
+        async fn get(&self, tx_id: &str, _max_size: u64) -> Result<Bytes, ArweaveError> {
+            match tx_id {
+                ARWEAVE_TX => Ok(r#"{"name": "token"}"#.into()),
+                UNAVAILABLE_TX => Err(ArweaveError::NotFound(
+                    tx_id.to_string(),
+                    Error::msg("unavail"),
+                )),
+                _ => unreachable!("unknown transaction"),
+            }
+        }
+
+        fn invalidate_cache(&self) {
+            unreachable!("invalidate cache");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_arweave() {
+        let check = |deployment: &str| {
+            let deployment = Cid::from_str(deployment).unwrap();
+            async move {
+                crate::check(
+                    &MockIpfs,
+                    &MockArweave,
+                    deployment,
+                    &["mainnet".into()],
+                    &["file/arweave".into()],
+                    &[],
+                    &[],
+                    &file_size_limits(),
+                    &version_bounds(),
+                )
+                .await
+            }
+        };
+
+        assert!(check(ARWEAVE_DS).await.is_ok());
+        match check(ARWEAVE_DS_UNAVAILABLE).await {
+            Err(CheckError::Invalid(e @ Invalid::Unavailable(..))) => assert_eq!(
+                e.to_string(),
+                format!("unavailable file: ar://{} (unavail)", UNAVAILABLE_TX)
+            ),
+            _ => panic!("expected an unavailable arweave file"),
+        }
+    }
+
+    const GRAFT_A: &str = "QmWt3graftAzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+    const GRAFT_B: &str = "QmWt3graftBzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+    const GRAFT_C: &str = "QmWt3graftCzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+    const GRAFT_D: &str = "QmWt3graftDzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+    const GRAFT_E: &str = "QmWt3graftEzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz";
+
+    /// Serves valid manifests grafted onto other deployments:
+    /// - GRAFT_A and GRAFT_B onto each other
+    /// - GRAFT_C onto ONE, which is valid
+    /// - GRAFT_D onto TWO, whose manifest is missing
+    /// - GRAFT_E onto GRAFT_C, declaring the `fullTextSearch` feature
+    struct MockGraftIpfs;
+
+    #[async_trait]
+    impl Ipfs for MockGraftIpfs {
+        async fn cat(&self, cid: Cid, max_size: u64) -> Result<Bytes, IpfsError> {
+            let valid_manifest = include_str!("test_files/valid.yaml");
+            let grafted = |base: &str| {
+                Ok(format!("{}graft:\n  base: {}\n  block: 10\n", valid_manifest, base).into())
+            };
+            match cid.to_string().as_str() {
+                GRAFT_A => grafted(GRAFT_B),
+                GRAFT_B => grafted(GRAFT_A),
+                GRAFT_C => grafted(ONE),
+                GRAFT_D => grafted(TWO),
+                GRAFT_E => Ok(format!(
+                    "{}features:\n  - fullTextSearch\ngraft:\n  base: {}\n  block: 10\n",
+                    valid_manifest, GRAFT_C
+                )
+                .into()),
+                _ => MockIpfs.cat(cid, max_size).await,
+            }
+        }
+
+        fn invalidate_cache(&self) {
+            unreachable!("invalidate cache");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_graft() {
+        let check = |deployment: &str, supported_features: &'static [String]| {
+            let deployment = Cid::from_str(deployment).unwrap();
+            async move {
+                crate::check(
+                    &MockGraftIpfs,
+                    &MockArweave,
+                    deployment,
+                    &["mainnet".into()],
+                    &["ethereum/contract".into()],
+                    supported_features,
+                    &[],
+                    &file_size_limits(),
+                    &version_bounds(),
+                )
+                .await
+            }
+        };
+
+        assert!(check(GRAFT_C, &[]).await.is_ok());
+        match check(GRAFT_A, &[]).await {
+            Err(CheckError::Invalid(Invalid::GraftCycle(base))) => {
+                assert_eq!(base.to_string(), GRAFT_A)
+            }
+            _ => panic!("expected a graft cycle"),
+        }
+        match check(GRAFT_D, &[]).await {
+            Err(CheckError::Invalid(Invalid::BadGraftBase(base, e))) => {
+                assert_eq!(base.to_string(), TWO);
+                assert_eq!(e.kind(), "Unavailable");
+                let invalid = Invalid::BadGraftBase(base, e);
+                assert_eq!(invalid.kind(), "BadGraftBase");
+                assert!(invalid.is_unavailable());
+            }
+            _ => panic!("expected a bad graft base"),
+        }
+        match check(GRAFT_E, &[]).await {
+            Err(CheckError::Invalid(Invalid::UnsupportedFeature(feature))) => {
+                assert_eq!(feature, "fullTextSearch")
+            }
+            _ => panic!("expected an unsupported feature"),
+        }
+    }
+
+    /// Checks the verdicts of `MockSubgraph`, and whether the new denials were held back.
+    struct MockReportWriter {
+        held_back: bool,
+    }
+
+    impl ReportWriter for MockReportWriter {
+        fn write(&self, verdicts: &[Verdict]) -> Result<(), Error> {
+            assert!(verdicts.iter().all(|v| v.block_number == BLOCK));
+            assert!(verdicts
+                .iter()
+                .all(|v| v.held_back == (self.held_back && v.deny && !v.previous_deny)));
+            let verdicts = verdicts
+                .iter()
+                .map(|v| (v.cid.as_str(), v.previous_deny, v.deny, v.invalid_kind))
+                .collect::<Vec<_>>();
+
+            assert_eq!(verdicts.len(), 11);
+            assert_eq!(verdicts[0], (ZERO, true, true, Some("Unavailable")));
+            assert_eq!(verdicts[1], (ONE, false, false, None));
+            assert_eq!(verdicts[3], (THREE, false, true, Some("Unavailable")));
+            assert_eq!(verdicts[4], (FOUR, true, false, None));
+            assert_eq!(verdicts[5], (FIVE, false, true, Some("AbiParseError")));
+            assert_eq!(verdicts[6], (SIX, false, true, Some("ManifestParseError")));
+            assert_eq!(
+                verdicts[7],
+                (SEVEN, false, true, Some("UnsupportedNetwork"))
+            );
+            assert_eq!(verdicts[8], (SUBSTREAM, false, true, Some("BadCid")));
+            assert_eq!(verdicts[9], (FILE_DS, false, false, None));
+            assert_eq!(verdicts[10], (SUBSTREAM_PACKAGE, false, false, None));
+
+            Ok(())
+        }
+    }
+
     struct MockStateManager;
 
     #[async_trait]
@@ -157,13 +911,30 @@ mod tests {
                 })
                 .collect::<Vec<_>>();
 
-            assert!(denied_status.len() == 6);
+            assert!(denied_status.len() == 7);
             assert_eq!(denied_status[0], (TWO.to_string(), true));
             assert_eq!(denied_status[1], (THREE.to_string(), true));
             assert_eq!(denied_status[2], (FOUR.to_string(), false));
             assert_eq!(denied_status[3], (FIVE.to_string(), true));
             assert_eq!(denied_status[4], (SIX.to_string(), true));
             assert_eq!(denied_status[5], (SEVEN.to_string(), true));
+            assert_eq!(denied_status[6], (SUBSTREAM.to_string(), true));
+
+            Ok(())
+        }
+    }
+
+    struct MockUndenyStateManager;
+
+    #[async_trait]
+    impl contract::StateManager for MockUndenyStateManager {
+        async fn deny_many(&self, denied_status: Vec<([u8; 32], bool)>) -> Result<(), Error> {
+            let denied_status = denied_status
+                .into_iter()
+                .map(|(id, deny)| (bytes32_to_cid_v0(id).to_string(), deny))
+                .collect::<Vec<_>>();
+
+            assert_eq!(denied_status, vec![(FOUR.to_string(), false)]);
 
             Ok(())
         }
@@ -1,5 +1,9 @@
+use common::prelude::*;
 use tiny_cid::Cid;
 
+// The multihash code of sha2-256.
+const SHA2_256: u64 = 0x12;
+
 pub fn bytes32_to_cid_v0(bytes32: [u8; 32]) -> Cid {
     let mut cidv0: [u8; 34] = [0; 34];
 
@@ -13,16 +17,40 @@ pub fn bytes32_to_cid_v0(bytes32: [u8; 32]) -> Cid {
     Cid::read_bytes(cidv0.as_ref()).unwrap()
 }
 
-// Panics if `cid` version is not v0.
-#[allow(dead_code)]
-pub fn cid_v0_to_bytes32(cid: &Cid) -> [u8; 32] {
-    assert!(cid.version() == tiny_cid::Version::V0);
-    let cid_bytes = cid.to_bytes();
+/// The bytes32 deployment ID of `cid`, which is the sha2-256 digest of a dag-pb manifest. A CIDv0
+/// and a CIDv1 of the same manifest have the same deployment ID. CIDs with other codecs or hash
+/// functions, such as raw CIDv1, can't be represented.
+pub fn cid_to_bytes32(cid: &Cid) -> Result<[u8; 32], Error> {
+    let hash = cid.hash();
+    if cid.codec() != tiny_cid::DAG_PROTOBUF || hash.code() != SHA2_256 {
+        return Err(anyhow!(
+            "{} is not a dag-pb sha2-256 CID, and can't be represented as a deployment id",
+            cid
+        ));
+    }
+    // Unwrap: sha2-256 digests are 32 bytes long.
+    Ok(hash.digest().try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_cid_to_bytes32() {
+        let v0 = Cid::from_str("QmNQa1FSTXNHmrjjfgUW3Px3Vkke4oKiFWdigWkYSux2Pi").unwrap();
+        assert_eq!(cid_to_bytes32(&v0).unwrap(), [1; 32]);
+        assert_eq!(bytes32_to_cid_v0([1; 32]), v0);
 
-    // A CIDv0 in byte form is 34 bytes long, starting with 0x1220.
-    assert_eq!(cid_bytes.len(), 34);
+        let v1 = Cid::new_v1(tiny_cid::DAG_PROTOBUF, *v0.hash());
+        assert!(v1.to_string().starts_with("bafy"));
+        assert_eq!(
+            cid_to_bytes32(&Cid::from_str(&v1.to_string()).unwrap()).unwrap(),
+            [1; 32]
+        );
 
-    let mut bytes: [u8; 32] = [0; 32];
-    bytes.copy_from_slice(&cid_bytes[2..]);
-    bytes
+        let raw = Cid::new_v1(tiny_cid::RAW, *v0.hash());
+        assert!(cid_to_bytes32(&raw).is_err());
+    }
 }
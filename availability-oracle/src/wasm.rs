@@ -0,0 +1,146 @@
+//! Checks on WASM mappings: that they validate, only import host functions graph-node provides,
+//! export the handlers the manifest names and don't ask for unreasonable amounts of memory.
+
+use crate::host_fns;
+use crate::Invalid;
+use semver::Version;
+use std::collections::HashSet;
+use wasmparser::{ExternalKind, Payload, TypeRef, Validator};
+
+/// Limit on the initial size of a memory, in 64 KiB pages. 1 GiB.
+const MAX_MEMORY_PAGES: u64 = 16 * 1024;
+
+/// Limit on the initial size of a table, in elements.
+const MAX_TABLE_ELEMENTS: u64 = 1_000_000;
+
+/// Checks a mapping of `api_version` which is expected to export the `handlers`.
+pub fn check_mapping<'a>(
+    wasm: &[u8],
+    api_version: &Version,
+    forbidden_host_fns: &[String],
+    handlers: impl IntoIterator<Item = &'a str>,
+) -> Result<(), Invalid> {
+    Validator::new()
+        .validate_all(wasm)
+        .map_err(|e| Invalid::WasmValidationError(e.into()))?;
+
+    let mut exports = HashSet::new();
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        let payload = payload.map_err(|e| Invalid::WasmParseError(e.into()))?;
+        match payload {
+            Payload::ImportSection(s) => {
+                for import in s {
+                    let import = import.map_err(|e| Invalid::WasmParseError(e.into()))?;
+                    match import.ty {
+                        TypeRef::Func(_)
+                            if !host_fns::is_provided(import.name, api_version)
+                                || host_fns::is_forbidden(import.name, forbidden_host_fns) =>
+                        {
+                            return Err(Invalid::ForbiddenApi(
+                                import.module.to_string(),
+                                import.name.to_string(),
+                            ));
+                        }
+                        TypeRef::Memory(memory) => check_memory(memory.initial)?,
+                        TypeRef::Table(table) => check_table(table.initial as u64)?,
+                        _ => (),
+                    }
+                }
+            }
+            Payload::MemorySection(s) => {
+                for memory in s {
+                    let memory = memory.map_err(|e| Invalid::WasmParseError(e.into()))?;
+                    check_memory(memory.initial)?;
+                }
+            }
+            Payload::TableSection(s) => {
+                for table in s {
+                    let table = table.map_err(|e| Invalid::WasmParseError(e.into()))?;
+                    check_table(table.ty.initial as u64)?;
+                }
+            }
+            Payload::ExportSection(s) => {
+                for export in s {
+                    let export = export.map_err(|e| Invalid::WasmParseError(e.into()))?;
+                    if export.kind == ExternalKind::Func {
+                        exports.insert(export.name);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    for handler in handlers {
+        if !exports.contains(handler) {
+            return Err(Invalid::MissingHandlerExport(handler.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_memory(pages: u64) -> Result<(), Invalid> {
+    match pages > MAX_MEMORY_PAGES {
+        true => Err(Invalid::ExcessiveMemory(
+            "memory pages",
+            pages,
+            MAX_MEMORY_PAGES,
+        )),
+        false => Ok(()),
+    }
+}
+
+fn check_table(elements: u64) -> Result<(), Invalid> {
+    match elements > MAX_TABLE_ELEMENTS {
+        true => Err(Invalid::ExcessiveMemory(
+            "table elements",
+            elements,
+            MAX_TABLE_ELEMENTS,
+        )),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WASM: &[u8] = include_bytes!("test_files/Contract.wasm");
+
+    #[test]
+    fn test_check_mapping() {
+        let api_version = Version::new(0, 0, 4);
+        assert!(check_mapping(WASM, &api_version, &[], ["handleTrigger"]).is_ok());
+
+        match check_mapping(WASM, &api_version, &[], ["handleTrigger", "handleCall"]) {
+            Err(Invalid::MissingHandlerExport(handler)) => assert_eq!(handler, "handleCall"),
+            _ => panic!("expected a missing handler"),
+        }
+
+        // Cut off in the middle of the code section.
+        match check_mapping(&WASM[..WASM.len() / 2], &api_version, &[], []) {
+            Err(Invalid::WasmValidationError(_)) => (),
+            _ => panic!("expected a validation error"),
+        }
+
+        // A module importing `env.memory` with an initial size of 16385 pages.
+        let imported_memory = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+            0x02, 0x11, 0x01, // import section with one import
+            0x03, 0x65, 0x6e, 0x76, // "env"
+            0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, // "memory"
+            0x02, 0x00, 0x81, 0x80, 0x01, // memory with no maximum
+        ];
+        match check_mapping(&imported_memory, &api_version, &[], []) {
+            Err(Invalid::ExcessiveMemory("memory pages", 16385, MAX_MEMORY_PAGES)) => (),
+            _ => panic!("expected excessive memory"),
+        }
+
+        assert!(check_memory(MAX_MEMORY_PAGES).is_ok());
+        assert!(matches!(
+            check_memory(MAX_MEMORY_PAGES + 1),
+            Err(Invalid::ExcessiveMemory(..))
+        ));
+    }
+}
@@ -15,6 +15,12 @@ struct Config {
     epoch_block_oracle_subgraph_deployment_id: String,
     subgraph_availability_manager_contract: String,
     oracle_index: String,
+    // Absent from payloads written before file size limits existed.
+    #[serde(default)]
+    max_file_sizes: String,
+    // Absent from payloads written before version bounds existed.
+    #[serde(default)]
+    version_bounds: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -42,9 +48,58 @@ pub fn json_to_calldata(json: serde_json::Value) -> anyhow::Result<Vec<u8>> {
             Token::String(data.config.epoch_block_oracle_subgraph_deployment_id),
             Token::String(data.config.subgraph_availability_manager_contract),
             Token::String(data.config.oracle_index),
+            Token::String(data.config.max_file_sizes),
+            Token::String(data.config.version_bounds),
         ]),
     ];
 
     let encoded_data = function.encode_input(&tokens)?;
-    return Ok(encoded_data);
+    Ok(encoded_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_to_calldata() {
+        let json = serde_json::json!({
+            "version": "v1.0.0",
+            "config": {
+                "ipfs_concurrency": "4",
+                "ipfs_timeout": "10000",
+                "min_signal": "100",
+                "period": "300",
+                "grace_period": "0",
+                "supported_data_source_kinds": "ethereum",
+                "network_subgraph_deployment_id": "Qm123",
+                "epoch_block_oracle_subgraph_deployment_id": "Qm456",
+                "subgraph_availability_manager_contract": "0x123",
+                "oracle_index": "0",
+                "max_file_sizes": "manifest:1024,schema:1024,abi:1024,wasm:2048",
+                "version_bounds": "spec:0.0.2-1.3.0,api:0.0.2-0.0.9",
+            }
+        });
+        let calldata = json_to_calldata(json).unwrap();
+
+        // Decodes strictly with the declared ABI.
+        let contract = Contract::load(ABI_BYTES).unwrap();
+        let function = contract.function("submitConfig").unwrap();
+        assert_eq!(calldata[..4], function.short_signature());
+        let tokens = function.decode_input(&calldata[4..]).unwrap();
+        assert_eq!(function.encode_input(&tokens).unwrap(), calldata);
+        match &tokens[1] {
+            Token::Tuple(config) => {
+                assert_eq!(
+                    config[10],
+                    Token::String("manifest:1024,schema:1024,abi:1024,wasm:2048".to_string())
+                );
+                assert_eq!(
+                    config[11],
+                    Token::String("spec:0.0.2-1.3.0,api:0.0.2-0.0.9".to_string())
+                );
+            }
+            _ => panic!("expected the config tuple"),
+        }
+    }
 }
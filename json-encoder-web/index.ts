@@ -18,7 +18,9 @@ const samplePayload = `{
 		"network_subgraph_deployment_id": "QmSWxvd8SaQK6qZKJ7xtfxCCGoRzGnoi2WNzmJYYJW9BXY",
 		"epoch_block_oracle_subgraph_deployment_id": "QmQEGDTb3xeykCXLdWx7pPX3qeeGMUvHmGWP4SpMkv5QJf",
 		"subgraph_availability_manager_contract": "CONTRACT_ADDRESS",
-		"oracle_index": "ORACLE_INDEX"
+		"oracle_index": "ORACLE_INDEX",
+		"max_file_sizes": "manifest:5242880,schema:5242880,abi:5242880,wasm:26214400",
+		"version_bounds": "spec:0.0.2-1.3.0,api:0.0.2-0.0.9"
 	}
 }
 `;